@@ -0,0 +1,4240 @@
+//! The HTML to Markdown conversion itself, independent of the Worker runtime.
+//! Outlines, sections and the other structured outputs are only reachable
+//! through the fetch handler, so they're compiled with the `worker` feature.
+
+use serde::{Deserialize, Serialize};
+use html5ever::parse_document;
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+#[cfg(feature = "worker")]
+use xml5ever::driver::parse_document as parse_xml_document;
+use lazy_static::lazy_static;
+use regex::{Regex, RegexBuilder};
+use url::Url;
+use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::fmt;
+
+/// Conversion options. Every field has a default, so a config can be built from
+/// `ConvertConfig::default()` or deserialized from a partial JSON object.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ConvertConfig {
+    pub include_links: bool,
+    pub clean_whitespace: bool,
+    pub cleaning_rules: CleaningRules,
+    pub preserve_headings: bool,
+    pub include_metadata: bool,
+    pub max_heading_level: u8,
+    pub convert_feeds: bool,
+    pub max_table_columns: Option<usize>,
+    pub content_selectors: Vec<String>,
+    pub abbreviation_glossary: bool,
+    pub image_src_attributes: Vec<String>,
+    pub username: Option<String>,
+    pub password: Option<Redacted>,
+    pub default_summary: Option<String>,
+    pub frontmatter_delimiter: String,
+    pub hard_break_style: HardBreakStyle,
+    pub whitespace_mode: Option<WhitespaceMode>,
+    pub split_by_heading: Option<u8>,
+    pub ordered_delimiter: OrderedDelimiter,
+    pub summary_words: Option<usize>,
+    pub list_type_markers: bool,
+    pub resolve_truncated_links: bool,
+    pub strip_image_query: bool,
+    pub indent_plain_code: bool,
+    pub br_paragraphs: bool,
+    pub follow_meta_refresh: bool,
+    pub link_domain_badge: bool,
+    pub bold_row_headers: bool,
+    pub final_newline: bool,
+    pub include_fetched_at: bool,
+    pub code_tag_styles: BTreeMap<String, CodeStyle>,
+    pub collapse_wrappers: bool,
+    pub canonical_image_urls: bool,
+    pub image_url_rewrites: Vec<UrlRewrite>,
+    pub link_format: LinkFormat,
+    pub skip_empty_blocks: bool,
+    pub style_emphasis: bool,
+    pub inventory: bool,
+    pub ascii_tables: bool,
+    pub landmark_headings: bool,
+    pub max_sections: Option<usize>,
+    pub canonical_languages: bool,
+    pub language_aliases: BTreeMap<String, String>,
+    pub metadata_format: MetadataFormat,
+    pub article_separation: Option<ArticleSeparation>,
+    pub include_breadcrumbs: bool,
+    pub quote_locale: Option<String>,
+    pub timeout_ms: u32,
+    pub emoji_as_text: bool,
+    pub max_link_density: Option<f32>,
+    pub source_map: bool,
+    pub include_contact: bool,
+    pub trim_heading_numbers: bool,
+    pub code_highlight_lines: bool,
+    pub request_headers: BTreeMap<String, String>,
+    pub strip_heading_markers: bool,
+    pub cache_ttl: u64,
+    pub group_references_by_domain: bool,
+    pub strip_tracking_params: bool,
+    pub skip_emoji_items: bool,
+    pub normalize_heading_levels: bool,
+    pub escape_markdown: bool,
+    pub replacements: Vec<Replacement>,
+    pub divider_selectors: Vec<String>,
+    pub external_link_footnotes: bool,
+    pub include_pagination: bool,
+    pub max_redirects: u8,
+    pub max_bytes: usize,
+    pub extra_frontmatter: BTreeMap<String, String>,
+    pub strip_html_attributes: bool,
+}
+
+impl Default for ConvertConfig {
+    fn default() -> Self {
+        Self {
+            include_links: false,
+            clean_whitespace: false,
+            cleaning_rules: CleaningRules::default(),
+            preserve_headings: false,
+            include_metadata: false,
+            max_heading_level: 0,
+            convert_feeds: false,
+            max_table_columns: None,
+            content_selectors: Vec::new(),
+            abbreviation_glossary: false,
+            image_src_attributes: Vec::new(),
+            username: None,
+            password: None,
+            default_summary: None,
+            frontmatter_delimiter: "---".to_string(),
+            hard_break_style: HardBreakStyle::default(),
+            whitespace_mode: None,
+            split_by_heading: None,
+            ordered_delimiter: OrderedDelimiter::default(),
+            summary_words: None,
+            list_type_markers: false,
+            resolve_truncated_links: false,
+            strip_image_query: false,
+            indent_plain_code: false,
+            br_paragraphs: false,
+            follow_meta_refresh: false,
+            link_domain_badge: false,
+            bold_row_headers: false,
+            final_newline: true,
+            include_fetched_at: false,
+            code_tag_styles: BTreeMap::new(),
+            collapse_wrappers: false,
+            canonical_image_urls: false,
+            image_url_rewrites: Vec::new(),
+            link_format: LinkFormat::default(),
+            skip_empty_blocks: false,
+            style_emphasis: false,
+            inventory: false,
+            ascii_tables: false,
+            landmark_headings: false,
+            max_sections: None,
+            canonical_languages: false,
+            language_aliases: BTreeMap::new(),
+            metadata_format: MetadataFormat::default(),
+            article_separation: None,
+            include_breadcrumbs: false,
+            quote_locale: None,
+            timeout_ms: 10000,
+            emoji_as_text: true,
+            max_link_density: None,
+            source_map: false,
+            include_contact: false,
+            trim_heading_numbers: false,
+            code_highlight_lines: false,
+            request_headers: BTreeMap::new(),
+            strip_heading_markers: false,
+            cache_ttl: 3600,
+            group_references_by_domain: false,
+            strip_tracking_params: false,
+            skip_emoji_items: false,
+            normalize_heading_levels: false,
+            escape_markdown: true,
+            replacements: Vec::new(),
+            divider_selectors: Vec::new(),
+            external_link_footnotes: false,
+            include_pagination: false,
+            max_redirects: 5,
+            max_bytes: 5 * 1024 * 1024,
+            extra_frontmatter: BTreeMap::new(),
+            strip_html_attributes: true,
+        }
+    }
+}
+
+/// A named bundle of options; fields set explicitly in a request override it.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Blog posts and news: headings, links and front matter, without empty wrappers
+    Article,
+    /// Technical documentation: headings, links and normalized code languages
+    Docs,
+    /// Just the text: no scripts, styles or comments, and whitespace collapsed
+    Plain,
+}
+
+impl Preset {
+    pub fn config(self) -> ConvertConfig {
+        let defaults = ConvertConfig::default();
+        match self {
+            Preset::Article => ConvertConfig {
+                preserve_headings: true,
+                max_heading_level: 6,
+                include_metadata: true,
+                include_links: true,
+                skip_empty_blocks: true,
+                ..defaults
+            },
+            Preset::Docs => ConvertConfig {
+                preserve_headings: true,
+                max_heading_level: 6,
+                include_links: true,
+                canonical_languages: true,
+                code_highlight_lines: true,
+                ..defaults
+            },
+            Preset::Plain => ConvertConfig {
+                cleaning_rules: CleaningRules {
+                    remove_scripts: true,
+                    remove_styles: true,
+                    remove_comments: true,
+                    preserve_line_breaks: false,
+                },
+                whitespace_mode: Some(WhitespaceMode::Inline),
+                skip_empty_blocks: true,
+                ..defaults
+            },
+        }
+    }
+}
+
+impl ConvertConfig {
+    /// `whitespace_mode` wins when set; otherwise the older `clean_whitespace` flag
+    /// picks between the two original behaviors.
+    fn whitespace_mode(&self) -> WhitespaceMode {
+        self.whitespace_mode.unwrap_or(if self.clean_whitespace {
+            WhitespaceMode::Aggressive
+        } else {
+            WhitespaceMode::Off
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum HardBreakStyle {
+    #[default]
+    Spaces,
+    Backslash,
+    Html,
+}
+
+impl HardBreakStyle {
+    fn marker(self) -> &'static str {
+        match self {
+            HardBreakStyle::Spaces => "  \n",
+            HardBreakStyle::Backslash => "\\\n",
+            HardBreakStyle::Html => "<br>\n",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum WhitespaceMode {
+    /// Text is emitted exactly as it appears in the source.
+    Off,
+    /// Runs collapse to a single space inside text, but spacing between inline
+    /// elements is kept and block boundaries are never merged.
+    Inline,
+    /// Text is trimmed and every whitespace run in the output becomes a break.
+    Aggressive,
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy)]
+pub enum OrderedDelimiter {
+    #[default]
+    #[serde(rename = ".")]
+    Period,
+    #[serde(rename = ")")]
+    Paren,
+}
+
+impl OrderedDelimiter {
+    fn as_str(self) -> &'static str {
+        match self {
+            OrderedDelimiter::Period => ".",
+            OrderedDelimiter::Paren => ")",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataFormat {
+    /// A `# title` line followed by a delimited `Key: value` block
+    #[default]
+    Custom,
+    /// `title: X | author: Y | date: Z` on a single line
+    Inline,
+    /// YAML front matter between `---` fences, as static site generators read it
+    Yaml,
+}
+
+/// How pages with several top-level `<article>`s (listings, feeds) are split up.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ArticleSeparation {
+    /// A `---` rule between articles
+    Rule,
+    /// A JSON array with one markdown document per article
+    Array,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkFormat {
+    #[default]
+    Markdown,
+    /// Same-site links and images become Obsidian `[[wikilinks]]` and `![[embeds]]`
+    Wikilink,
+    /// Links become `[text][n]`, with the definitions collected at the end
+    Reference,
+}
+
+/// A find/replace applied to the finished markdown. `pattern` is literal text
+/// unless `regex` is set, in which case `replacement` may use `$1`-style groups.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Replacement {
+    pub pattern: String,
+    pub replacement: String,
+    #[serde(default)]
+    pub regex: bool,
+}
+
+/// A regex replacement applied to image URLs; `replacement` may use `$1`-style groups.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UrlRewrite {
+    pub pattern: String,
+    pub replacement: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeStyle {
+    Backticks,
+    Html,
+    Italic,
+}
+
+/// A credential that prints as `[redacted]` so it can't leak through `{:?}` logging.
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(transparent)]
+pub struct Redacted(pub(crate) String);
+
+impl From<String> for Redacted {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct CleaningRules {
+    pub remove_scripts: bool,
+    pub remove_styles: bool,
+    pub remove_comments: bool,
+    pub preserve_line_breaks: bool,
+}
+
+pub(crate) struct MetadataHandler {
+    title: Option<String>,
+    /// The `<title>` element, used when there's no `og:title`
+    document_title: Option<String>,
+    author: Option<String>,
+    /// The text of the first `rel="author"` link, used when no meta tag names the author
+    author_link: Option<String>,
+    date: Option<String>,
+    description: Option<String>,
+    summary: Option<String>,
+    pub(crate) fetched_at: Option<String>,
+    tags: Vec<String>,
+    breadcrumbs: Vec<String>,
+    email: Option<String>,
+    phone: Option<String>,
+    address: Option<String>,
+    next_page: Option<String>,
+    prev_page: Option<String>,
+    linked_data: LinkedData,
+}
+
+/// Article fields from `<script type="application/ld+json">` blocks. The first
+/// value found for each field wins, and meta tags win over all of them.
+#[derive(Default)]
+struct LinkedData {
+    headline: Option<String>,
+    author: Option<String>,
+    date_published: Option<String>,
+    description: Option<String>,
+    keywords: Vec<String>,
+}
+
+impl LinkedData {
+    /// Reads one script's JSON, which may be a single object, an array of them,
+    /// or an `@graph`. Malformed JSON is ignored.
+    fn read(&mut self, json: &str) {
+        if let Ok(value) = serde_json::from_str::<serde_json::Value>(json) {
+            self.visit(&value);
+        }
+    }
+
+    fn visit(&mut self, value: &serde_json::Value) {
+        use serde_json::Value;
+
+        let object = match value {
+            Value::Array(items) => return items.iter().for_each(|item| self.visit(item)),
+            Value::Object(object) => object,
+            _ => return,
+        };
+        if let Some(graph) = object.get("@graph") {
+            self.visit(graph);
+        }
+
+        // Only article-like objects; a page's Organization or WebSite has a
+        // description too, but not the one we want
+        let is_article = object.contains_key("headline") || match object.get("@type") {
+            Some(Value::String(kind)) => kind.ends_with("Article") || kind == "BlogPosting",
+            Some(Value::Array(kinds)) => kinds.iter().filter_map(Value::as_str).any(|kind| kind.ends_with("Article") || kind == "BlogPosting"),
+            _ => false,
+        };
+        if !is_article {
+            return;
+        }
+
+        let text = |key: &str| object.get(key).and_then(Value::as_str).map(str::trim).filter(|text| !text.is_empty()).map(str::to_string);
+        if self.headline.is_none() {
+            self.headline = text("headline");
+        }
+        if self.date_published.is_none() {
+            self.date_published = text("datePublished");
+        }
+        if self.description.is_none() {
+            self.description = text("description");
+        }
+        if self.author.is_none() {
+            let authors = object.get("author").map(json_names).unwrap_or_default();
+            self.author = (!authors.is_empty()).then(|| authors.join(", "));
+        }
+        if self.keywords.is_empty() {
+            self.keywords = match object.get("keywords") {
+                Some(Value::String(keywords)) => keywords.split(',').map(str::trim).filter(|keyword| !keyword.is_empty()).map(str::to_string).collect(),
+                Some(Value::Array(keywords)) => keywords.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+                _ => Vec::new(),
+            };
+        }
+    }
+}
+
+/// Names from a JSON-LD person field: a string, a `{"name": ...}` object, or a list of either.
+fn json_names(value: &serde_json::Value) -> Vec<String> {
+    use serde_json::Value;
+
+    match value {
+        Value::String(name) => vec![name.trim().to_string()],
+        Value::Object(person) => person.get("name").and_then(Value::as_str).map(|name| vec![name.trim().to_string()]).unwrap_or_default(),
+        Value::Array(people) => people.iter().flat_map(json_names).collect(),
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .filter(|name| !name.is_empty())
+    .collect()
+}
+
+impl MetadataHandler {
+    fn new() -> Self {
+        Self {
+            title: None,
+            document_title: None,
+            author: None,
+            author_link: None,
+            date: None,
+            description: None,
+            summary: None,
+            fetched_at: None,
+            tags: Vec::new(),
+            breadcrumbs: Vec::new(),
+            email: None,
+            phone: None,
+            address: None,
+            next_page: None,
+            prev_page: None,
+            linked_data: LinkedData::default(),
+        }
+    }
+
+    /// Fills what the meta tags left empty: from JSON-LD first, then from the
+    /// `<title>` element and `rel="author"` links.
+    fn apply_fallbacks(&mut self) {
+        let linked = std::mem::take(&mut self.linked_data);
+        self.title = self.title.take().or(linked.headline).or(self.document_title.take());
+        self.author = self.author.take().or(linked.author).or(self.author_link.take());
+        self.date = self.date.take().or(linked.date_published);
+        self.description = self.description.take().or(linked.description);
+        if self.tags.is_empty() {
+            self.tags = linked.keywords;
+        }
+    }
+
+    fn format_metadata(&self, config: &ConvertConfig) -> String {
+        match config.metadata_format {
+            MetadataFormat::Custom => self.format_custom(&config.frontmatter_delimiter),
+            MetadataFormat::Inline => self.format_inline(),
+            MetadataFormat::Yaml => self.format_yaml(&config.extra_frontmatter),
+        }
+    }
+
+    /// `extra` fields take precedence over extracted ones with the same key.
+    fn format_yaml(&self, extra: &BTreeMap<String, String>) -> String {
+        // A JSON string is also a valid YAML double-quoted scalar, escapes included
+        let quote = |value: &str| serde_json::to_string(value).unwrap_or_default();
+
+        let mut fields = String::new();
+        let scalars = [
+            ("title", &self.title),
+            ("author", &self.author),
+            ("date", &self.date),
+            ("description", &self.description),
+            ("summary", if self.description.is_none() { &self.summary } else { &None }),
+            ("email", &self.email),
+            ("phone", &self.phone),
+            ("address", &self.address),
+            ("next_page", &self.next_page),
+            ("prev_page", &self.prev_page),
+            ("fetched_at", &self.fetched_at),
+        ];
+        for (key, value) in scalars {
+            if let Some(value) = value.as_ref().filter(|_| !extra.contains_key(key)) {
+                fields.push_str(&format!("{}: {}\n", key, quote(value)));
+            }
+        }
+        for (key, values) in [("tags", &self.tags), ("breadcrumbs", &self.breadcrumbs)] {
+            if !values.is_empty() && !extra.contains_key(key) {
+                fields.push_str(&format!("{}:\n", key));
+                for value in values {
+                    fields.push_str(&format!("  - {}\n", quote(value)));
+                }
+            }
+        }
+        for (key, value) in extra {
+            let plain_key = !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            let key = if plain_key { key.clone() } else { quote(key) };
+            // Booleans and numbers stay bare so `draft: true` reads as a flag, not a string
+            let bare = matches!(value.as_str(), "true" | "false") || value.parse::<f64>().is_ok_and(f64::is_finite);
+            let value = if bare { value.clone() } else { quote(value) };
+            fields.push_str(&format!("{}: {}\n", key, value));
+        }
+
+        if fields.is_empty() {
+            return String::new();
+        }
+        format!("---\n{}---\n\n", fields)
+    }
+
+    fn format_inline(&self) -> String {
+        let summary = self.description.as_ref().map(|description| ("description", description))
+            .or_else(|| self.summary.as_ref().map(|summary| ("summary", summary)));
+        let tags = (!self.tags.is_empty()).then(|| self.tags.join(", "));
+        let breadcrumbs = (!self.breadcrumbs.is_empty()).then(|| self.breadcrumbs.join(" > "));
+
+        let fields: Vec<String> = [
+            self.title.as_ref().map(|title| ("title", title)),
+            self.author.as_ref().map(|author| ("author", author)),
+            self.date.as_ref().map(|date| ("date", date)),
+            summary,
+            tags.as_ref().map(|tags| ("tags", tags)),
+            breadcrumbs.as_ref().map(|breadcrumbs| ("breadcrumbs", breadcrumbs)),
+            self.email.as_ref().map(|email| ("email", email)),
+            self.phone.as_ref().map(|phone| ("phone", phone)),
+            self.address.as_ref().map(|address| ("address", address)),
+            self.next_page.as_ref().map(|next_page| ("next_page", next_page)),
+            self.prev_page.as_ref().map(|prev_page| ("prev_page", prev_page)),
+            self.fetched_at.as_ref().map(|fetched_at| ("fetched_at", fetched_at)),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| format!("{}: {}", key, value))
+        .collect();
+
+        if fields.is_empty() {
+            return String::new();
+        }
+        format!("{}\n\n", fields.join(" | "))
+    }
+
+    fn format_custom(&self, delimiter: &str) -> String {
+        let mut metadata = String::new();
+
+        if let Some(title) = &self.title {
+            metadata.push_str(&format!("# {}\n\n", title));
+        }
+
+        metadata.push_str(delimiter);
+        metadata.push('\n');
+
+        if let Some(author) = &self.author {
+            metadata.push_str(&format!("Author: {}\n", author));
+        }
+        if let Some(date) = &self.date {
+            metadata.push_str(&format!("Date: {}\n", date));
+        }
+        if let Some(description) = &self.description {
+            metadata.push_str(&format!("Description: {}\n", description));
+        } else if let Some(summary) = &self.summary {
+            metadata.push_str(&format!("Summary: {}\n", summary));
+        }
+        if !self.tags.is_empty() {
+            metadata.push_str(&format!("Tags: {}\n", self.tags.join(", ")));
+        }
+        if !self.breadcrumbs.is_empty() {
+            metadata.push_str(&format!("Breadcrumbs: {}\n", self.breadcrumbs.join(" > ")));
+        }
+        if let Some(email) = &self.email {
+            metadata.push_str(&format!("Email: {}\n", email));
+        }
+        if let Some(phone) = &self.phone {
+            metadata.push_str(&format!("Phone: {}\n", phone));
+        }
+        if let Some(address) = &self.address {
+            metadata.push_str(&format!("Address: {}\n", address));
+        }
+        if let Some(next_page) = &self.next_page {
+            metadata.push_str(&format!("Next page: {}\n", next_page));
+        }
+        if let Some(prev_page) = &self.prev_page {
+            metadata.push_str(&format!("Previous page: {}\n", prev_page));
+        }
+        if let Some(fetched_at) = &self.fetched_at {
+            metadata.push_str(&format!("Fetched at: {}\n", fetched_at));
+        }
+
+        metadata.push_str(delimiter);
+        metadata.push_str("\n\n");
+        metadata
+    }
+}
+
+pub(crate) struct MarkdownFormatter {
+    pub(crate) config: ConvertConfig,
+    content: String,
+    list_type_stack: Vec<ListType>,
+    article_depth: usize,
+    articles_seen: usize,
+    blockquote_depth: usize,
+    in_table: bool,
+    table_columns: Vec<String>,
+    table_header_seen: bool,
+    table_alignments: Vec<Option<Alignment>>,
+    table_rows: Vec<Vec<String>>,
+    current_row: Vec<String>,
+    pub(crate) metadata: MetadataHandler,
+    in_code_block: bool,
+    in_pre: bool,
+    in_heading: bool,
+    /// Source levels of the open headings, for `normalize_heading_levels`
+    heading_stack: Vec<usize>,
+    headings: Vec<(u8, String)>,
+    abbreviations: Vec<(String, String)>,
+    references: Vec<(String, Option<String>)>,
+    footnotes: Vec<String>,
+    last_break: Option<usize>,
+    image_rewrites: Vec<(Regex, String)>,
+    replacements: Vec<(Regex, String)>,
+    base_url: Option<Url>,
+    pub(crate) links: Vec<LinkEntry>,
+    pub(crate) images: Vec<ImageEntry>,
+    #[cfg(feature = "worker")]
+    element_path: Vec<String>,
+    #[cfg(feature = "worker")]
+    provenance: Vec<Provenance>,
+    #[cfg(feature = "worker")]
+    elements_seen: usize,
+}
+
+/// What one source element emitted, recorded for `source_map`. `order` counts
+/// elements as they're entered, i.e. document order.
+#[cfg(feature = "worker")]
+struct Provenance {
+    order: usize,
+    tag: String,
+    path: String,
+    emitted: String,
+}
+
+#[derive(Clone, Copy)]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+enum ListType {
+    /// Start number, marker style, and whether the numbers count down (`<ol reversed>`)
+    Ordered(u8, ListMarker, bool),
+    Unordered,
+}
+
+/// Marker style from `<ol type>`. Only `Decimal` is a real CommonMark list; the
+/// others are written as a run of marked paragraphs.
+#[derive(Clone, Copy, PartialEq)]
+enum ListMarker {
+    Decimal,
+    UpperAlpha,
+    LowerAlpha,
+    UpperRoman,
+    LowerRoman,
+}
+
+impl ListMarker {
+    fn from_type_attr(value: &str) -> Self {
+        match value {
+            "A" => ListMarker::UpperAlpha,
+            "a" => ListMarker::LowerAlpha,
+            "I" => ListMarker::UpperRoman,
+            "i" => ListMarker::LowerRoman,
+            _ => ListMarker::Decimal,
+        }
+    }
+
+    fn format(self, count: u32) -> String {
+        match self {
+            ListMarker::Decimal => count.to_string(),
+            ListMarker::UpperAlpha => to_alphabetic(count),
+            ListMarker::LowerAlpha => to_alphabetic(count).to_lowercase(),
+            ListMarker::UpperRoman => to_roman(count),
+            ListMarker::LowerRoman => to_roman(count).to_lowercase(),
+        }
+    }
+}
+
+fn to_alphabetic(mut count: u32) -> String {
+    let mut letters = Vec::new();
+    while count > 0 {
+        count -= 1;
+        letters.push((b'A' + (count % 26) as u8) as char);
+        count /= 26;
+    }
+    letters.iter().rev().collect()
+}
+
+fn to_roman(mut count: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+    ];
+
+    let mut roman = String::new();
+    for (value, numeral) in NUMERALS {
+        while count >= value {
+            roman.push_str(numeral);
+            count -= value;
+        }
+    }
+    roman
+}
+
+/// Block-level elements whose output `source_map` reports.
+#[cfg(feature = "worker")]
+const SOURCE_MAPPED_TAGS: &[&str] = &[
+    "h1", "h2", "h3", "h4", "h5", "h6", "p", "pre", "blockquote", "ul", "ol", "li",
+    "table", "img", "hr", "details",
+];
+
+/// Stands for one level of `>` on a line inside a `<blockquote>` until the
+/// outermost quote is finished.
+const QUOTE_LEVEL: char = '\u{1f}';
+
+/// Paragraphs shorter than this (bylines, captions) are skipped when deriving a summary.
+const SUMMARY_MIN_WORDS: usize = 5;
+
+/// Limits on `replacements`, so a request can't make every conversion crawl.
+/// Entries past the count, or with an oversized pattern or replacement, are skipped.
+const MAX_REPLACEMENTS: usize = 32;
+const MAX_REPLACEMENT_PATTERN_LEN: usize = 256;
+const MAX_REPLACEMENT_LEN: usize = 1024;
+const MAX_REPLACEMENT_REGEX_SIZE: usize = 1 << 20;
+
+lazy_static! {
+    static ref INLINE_TAGS: HashMap<&'static str, (&'static str, &'static str)> = {
+        let mut m = HashMap::new();
+        m.insert("strong", ("**", "**"));
+        m.insert("b", ("**", "**"));
+        m.insert("em", ("*", "*"));
+        m.insert("i", ("*", "*"));
+        m.insert("cite", ("*", "*"));
+        m.insert("mark", ("==", "=="));
+        m.insert("del", ("~~", "~~"));
+        m.insert("s", ("~~", "~~"));
+        m.insert("strike", ("~~", "~~"));
+        m.insert("ins", ("__", "__"));
+        m
+    };
+
+    /// Default rendering for code-like inline tags; `code_tag_styles` overrides per tag.
+    static ref CODE_TAGS: HashMap<&'static str, CodeStyle> = {
+        let mut m = HashMap::new();
+        m.insert("code", CodeStyle::Backticks);
+        m.insert("kbd", CodeStyle::Backticks);
+        m.insert("samp", CodeStyle::Backticks);
+        m.insert("tt", CodeStyle::Backticks);
+        m.insert("var", CodeStyle::Italic);
+        m
+    };
+
+    static ref LANGUAGE_ALIASES: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+        m.insert("js", "javascript");
+        m.insert("jsx", "javascript");
+        m.insert("ts", "typescript");
+        m.insert("tsx", "typescript");
+        m.insert("py", "python");
+        m.insert("rb", "ruby");
+        m.insert("rs", "rust");
+        m.insert("golang", "go");
+        m.insert("kt", "kotlin");
+        m.insert("cs", "csharp");
+        m.insert("c++", "cpp");
+        m.insert("sh", "bash");
+        m.insert("shell", "bash");
+        m.insert("zsh", "bash");
+        m.insert("yml", "yaml");
+        m.insert("md", "markdown");
+        m.insert("htm", "html");
+        m
+    };
+
+    static ref BLOCK_TAGS: HashMap<&'static str, BlockType> = {
+        let mut m = HashMap::new();
+        m.insert("p", BlockType::Paragraph);
+        m.insert("div", BlockType::Div);
+        m.insert("article", BlockType::Article);
+        m.insert("section", BlockType::Section);
+        m.insert("table", BlockType::Table);
+        m.insert("tr", BlockType::TableRow);
+        m.insert("td", BlockType::TableCell);
+        m.insert("th", BlockType::TableHeader);
+        m
+    };
+
+    static ref META_CHARSET_REGEX: Regex = Regex::new(r#"<meta[^>]*charset\s*=\s*["']?([a-z0-9_.:-]+)"#).unwrap();
+    static ref WHITESPACE_REGEX: Regex = Regex::new(r"\s+").unwrap();
+    static ref BLANK_LINES_REGEX: Regex = Regex::new(r"\n[ \t]*(?:\n[ \t]*)+\n").unwrap();
+    static ref HEADING_LINE_REGEX: Regex = Regex::new(r"^(#{1,6}) (.*)$").unwrap();
+    static ref HEADING_NUMBER_REGEX: Regex = Regex::new(r"^(?:\d{1,3}(?:\.\d{1,3})+\.?|\d{1,3}\.|[IVXLC]+\.)\s+").unwrap();
+    static ref URL_REGEX: Regex = Regex::new(r"^https?://").unwrap();
+
+    /// Resize variants on common image CDNs, rewritten back to the original upload.
+    static ref CDN_IMAGE_REWRITES: Vec<(Regex, &'static str)> = vec![
+        // Cloudinary: /image/upload/w_300,h_200,c_fill/v123/pic.jpg
+        (Regex::new(r"(/image/upload/)(?:[a-z]{1,3}_[^/]+/)+").unwrap(), "$1"),
+        // WordPress: /wp-content/uploads/2024/01/pic-300x200.jpg
+        (Regex::new(r"(/wp-content/uploads/.+)-\d+x\d+(\.(?:[jJ][pP][eE]?[gG]|[pP][nN][gG]|[gG][iI][fF]|[wW][eE][bB][pP]))").unwrap(), "$1$2"),
+        // Shopify: cdn.shopify.com/.../pic_300x.jpg
+        (Regex::new(r"(cdn\.shopify\.com/.+)_(?:\d+x\d*|\d*x\d+|small|medium|large|grande)(\.(?:[jJ][pP][eE]?[gG]|[pP][nN][gG]|[gG][iI][fF]|[wW][eE][bB][pP]))").unwrap(), "$1$2"),
+    ];
+}
+
+#[derive(Copy, Clone)]
+enum BlockType {
+    Paragraph,
+    Div,
+    Article,
+    Section,
+    Table,
+    TableRow,
+    TableCell,
+    TableHeader,
+}
+
+impl MarkdownFormatter {
+    fn new(config: ConvertConfig) -> Self {
+        // Patterns that fail to compile are skipped rather than failing the conversion
+        let image_rewrites = config.image_url_rewrites.iter()
+            .filter_map(|rewrite| Regex::new(&rewrite.pattern).ok().map(|regex| (regex, rewrite.replacement.clone())))
+            .collect();
+        let replacements = config.replacements.iter()
+            .take(MAX_REPLACEMENTS)
+            .filter(|entry| entry.pattern.len() <= MAX_REPLACEMENT_PATTERN_LEN && entry.replacement.len() <= MAX_REPLACEMENT_LEN)
+            .filter_map(|entry| {
+                let (pattern, replacement) = if entry.regex {
+                    (entry.pattern.clone(), entry.replacement.clone())
+                } else {
+                    (regex::escape(&entry.pattern), entry.replacement.replace('$', "$$"))
+                };
+                RegexBuilder::new(&pattern)
+                    .size_limit(MAX_REPLACEMENT_REGEX_SIZE)
+                    .build()
+                    .ok()
+                    .map(|regex| (regex, replacement))
+            })
+            .collect();
+
+        Self {
+            config,
+            content: String::with_capacity(4096),
+            list_type_stack: Vec::new(),
+            article_depth: 0,
+            articles_seen: 0,
+            blockquote_depth: 0,
+            in_table: false,
+            table_columns: Vec::new(),
+            table_header_seen: false,
+            table_alignments: Vec::new(),
+            table_rows: Vec::new(),
+            current_row: Vec::new(),
+            metadata: MetadataHandler::new(),
+            in_code_block: false,
+            in_pre: false,
+            in_heading: false,
+            heading_stack: Vec::new(),
+            headings: Vec::new(),
+            abbreviations: Vec::new(),
+            references: Vec::new(),
+            footnotes: Vec::new(),
+            last_break: None,
+            image_rewrites,
+            replacements,
+            base_url: None,
+            links: Vec::new(),
+            images: Vec::new(),
+            #[cfg(feature = "worker")]
+            element_path: Vec::new(),
+            #[cfg(feature = "worker")]
+            provenance: Vec::new(),
+            #[cfg(feature = "worker")]
+            elements_seen: 0,
+        }
+    }
+
+    fn should_skip_node(&self, handle: &Handle) -> bool {
+        if !self.config.cleaning_rules.remove_scripts
+           && !self.config.cleaning_rules.remove_styles
+           && !self.config.cleaning_rules.remove_comments {
+            return false;
+        }
+
+        match &handle.data {
+            NodeData::Element { name, .. } => {
+                let tag = name.local.as_ref();
+                (self.config.cleaning_rules.remove_scripts && tag == "script") ||
+                (self.config.cleaning_rules.remove_styles && tag == "style")
+            }
+            NodeData::Comment { .. } => self.config.cleaning_rules.remove_comments,
+            NodeData::ProcessingInstruction { .. } => true,
+            _ => false
+        }
+    }
+
+    fn clean_text(&self, text: &str) -> String {
+        if self.in_code_block {
+            return text.to_string();
+        }
+
+        // `&nbsp;` reaches us already decoded to U+00A0. It's output as a plain space,
+        // unless line breaks are preserved and no cleaning pass would collapse it anyway
+        let mode = self.config.whitespace_mode();
+        if mode == WhitespaceMode::Off {
+            return if self.config.cleaning_rules.preserve_line_breaks {
+                text.to_string()
+            } else {
+                text.replace('\u{a0}', " ")
+            };
+        }
+
+        let text = text.replace('\u{a0}', " ");
+
+        if mode == WhitespaceMode::Inline {
+            let collapsed = WHITESPACE_REGEX.replace_all(&text, " ");
+            // Don't start a line with a space or double up a space already written
+            return if self.content.is_empty() || self.content.ends_with(char::is_whitespace) {
+                collapsed.trim_start().to_string()
+            } else {
+                collapsed.into_owned()
+            };
+        }
+
+        let cleaned = WHITESPACE_REGEX
+            .replace_all(text.trim(), " ")
+            .to_string();
+
+        if cleaned.chars().all(char::is_whitespace) {
+            String::new()
+        } else {
+            cleaned
+        }
+    }
+
+    fn process_node(&mut self, handle: &Handle) {
+        #[cfg(feature = "worker")]
+        if self.config.source_map {
+            return self.process_mapped_node(handle);
+        }
+        self.emit_node(handle)
+    }
+
+    /// Emits the node and records what it produced for `source_map`.
+    #[cfg(feature = "worker")]
+    fn process_mapped_node(&mut self, handle: &Handle) {
+        let Some(tag) = element_name(handle) else {
+            return self.emit_node(handle);
+        };
+        self.element_path.push(tag.clone());
+        // Byte offsets can't order entries: links and other captures render into a
+        // cleared buffer, so their offsets restart at zero
+        let order = self.elements_seen;
+        self.elements_seen += 1;
+        let start = self.content.len();
+        self.emit_node(handle);
+
+        if SOURCE_MAPPED_TAGS.contains(&tag.as_str()) {
+            let emitted = self.content.get(start..).unwrap_or_default().trim();
+            if !emitted.is_empty() {
+                self.provenance.push(Provenance {
+                    order,
+                    tag: tag.clone(),
+                    path: self.element_path.join(" > "),
+                    emitted: emitted.to_string(),
+                });
+            }
+        }
+        self.element_path.pop();
+    }
+
+    fn emit_node(&mut self, handle: &Handle) {
+        if self.should_skip_node(handle) {
+            return;
+        }
+
+        match &handle.data {
+            NodeData::Element { name, attrs, .. } => {
+                let tag_name = name.local.as_ref();
+
+                // Dropping anything inside a table would shift the remaining cells
+                // under the wrong headers, so only whole tables are candidates
+                if let Some(max_density) = self.config.max_link_density.filter(|_| !self.in_table) {
+                    let is_block = BLOCK_TAGS.contains_key(tag_name)
+                        || matches!(tag_name, "ul" | "ol" | "nav" | "aside" | "header" | "footer");
+                    if is_block && link_density(handle).is_some_and(|density| density > max_density) {
+                        return;
+                    }
+                }
+
+                if self.config.landmark_headings && matches!(tag_name, "main" | "nav" | "section") {
+                    self.push_landmark_heading(handle, attrs);
+                }
+
+                match tag_name {
+                    name @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                        let level = name[1..].parse::<usize>().unwrap();
+                        let mut text = WHITESPACE_REGEX.replace_all(text_content(handle).trim(), " ").to_string();
+                        if self.config.trim_heading_numbers {
+                            text = trim_heading(&text);
+                        }
+                        self.headings.push((level as u8, text));
+
+                        let level = if self.config.normalize_heading_levels {
+                            self.normalized_heading_level(level)
+                        } else {
+                            level
+                        };
+                        if self.config.preserve_headings && level as u8 <= self.config.max_heading_level {
+                            self.process_header(handle, level);
+                        }
+                    }
+
+                    // The head's text (the `<title>`, unremoved styles or scripts) isn't
+                    // page content; the title reaches the metadata through `collect_metadata`
+                    "head" => {}
+
+                    "br" if self.in_code_block => self.content.push('\n'),
+                    // Pipe table rows can't span lines
+                    "br" if self.in_table => self.content.push_str("<br>"),
+                    "br" => self.process_line_break(),
+
+                    // Styled dividers (`div.divider`) are decoration, so their content is dropped
+                    _ if tag_name == "hr" || self.config.divider_selectors.iter().any(|selector| matches_selector(handle, selector)) => {
+                        self.add_double_newline();
+                        self.content.push_str("---");
+                        self.add_double_newline();
+                    }
+
+                    "a" => self.process_link(handle, attrs),
+                    "img" => self.process_image(handle, attrs),
+
+                    "pre" => {
+                        // Highlighters usually put the language on the inner <code>,
+                        // GitHub on a wrapping <div>
+                        let language = language_from_class(attrs)
+                            .or_else(|| find_child(handle, &["code"]).and_then(|code| match &code.data {
+                                NodeData::Element { attrs, .. } => language_from_class(attrs),
+                                _ => None,
+                            }))
+                            .or_else(|| parent_of(handle).and_then(|parent| match &parent.data {
+                                NodeData::Element { attrs, .. } => language_from_class(attrs),
+                                _ => None,
+                            }));
+
+                        // Prism and friends mark highlighted lines as `data-line="4-6, 9"`
+                        let highlight = if self.config.code_highlight_lines {
+                            highlighted_lines(attrs).or_else(|| find_child(handle, &["code"]).and_then(|code| match &code.data {
+                                NodeData::Element { attrs, .. } => highlighted_lines(attrs),
+                                _ => None,
+                            }))
+                        } else {
+                            None
+                        };
+
+                        self.in_code_block = true;
+                        self.in_pre = true;
+                        let start = self.content.len();
+                        self.process_children(handle);
+                        let code = self.content.split_off(start);
+                        self.in_pre = false;
+                        self.in_code_block = false;
+
+                        let ascii_table = if self.config.ascii_tables && language.is_none() && !self.in_table {
+                            parse_ascii_table(&code)
+                        } else {
+                            None
+                        };
+                        match ascii_table {
+                            Some(rows) => {
+                                self.table_rows = rows;
+                                self.table_header_seen = true;
+                                // ASCII tables carry no alignment; don't inherit an earlier table's
+                                self.table_alignments.clear();
+                                self.format_table();
+                            }
+                            None => self.push_code_block(&code, language.as_deref(), highlight.as_deref()),
+                        }
+                    }
+
+                    tag if CODE_TAGS.contains_key(tag) => self.process_code_tag(handle, attrs, tag),
+
+                    // Pipe tables can't hold lists or multiple paragraphs, so such tables stay HTML
+                    "table" if has_block_cells(handle) => {
+                        self.add_double_newline();
+                        self.content.push_str(&outer_html(handle, self.config.strip_html_attributes));
+                        self.add_double_newline();
+                    }
+
+                    "table" => {
+                        self.in_table = true;
+                        self.table_columns.clear();
+                        self.table_rows.clear();
+                        self.table_header_seen = false;
+                        self.table_alignments.clear();
+                        self.process_children(handle);
+                        self.format_table();
+                        self.in_table = false;
+                    }
+
+                    "tr" if self.in_table => {
+                        let in_thead = parent_of(handle).and_then(|parent| element_name(&parent)).as_deref() == Some("thead");
+                        if in_thead && self.table_rows.is_empty() {
+                            self.table_header_seen = true;
+                        }
+                        self.current_row.clear();
+                        self.process_children(handle);
+                        if !self.current_row.is_empty() {
+                            self.table_rows.push(self.current_row.clone());
+                        }
+                    }
+
+                    "th" | "td" if self.in_table => {
+                        if self.table_rows.is_empty() {
+                            if tag_name == "th" {
+                                self.table_header_seen = true;
+                            }
+                            // Column alignment comes from the header row
+                            self.table_alignments.push(cell_alignment(attrs));
+                        }
+                        // Render the cell in place so inline formatting lands inside it
+                        let start = self.content.len();
+                        self.process_children(handle);
+                        let cell = self.content.split_off(start);
+                        // Collapsed even with whitespace cleaning off, so padding lines up
+                        let mut cell = WHITESPACE_REGEX.replace_all(cell.trim(), " ").to_string();
+
+                        // Markdown has no row headers, so they're marked with bold instead
+                        let is_row_header = tag_name == "th" && attrs.borrow().iter()
+                            .any(|attr| attr.name.local.as_ref() == "scope" && attr.value.as_ref() == "row");
+                        if self.config.bold_row_headers && is_row_header && !cell.is_empty() {
+                            cell = format!("**{}**", cell);
+                        }
+
+                        self.current_row.push(cell);
+                    }
+
+                    "abbr" | "acronym" => {
+                        if self.config.abbreviation_glossary {
+                            self.record_abbreviation(handle, attrs);
+                        }
+                        self.process_children(handle);
+                    }
+
+                    "details" => self.process_details(handle),
+
+                    "video" | "audio" => self.process_media(handle, attrs, tag_name),
+
+                    "ul" => self.process_list(handle, ListType::Unordered),
+                    "ol" => {
+                        let marker = attrs.borrow().iter()
+                            .find(|attr| attr.name.local.as_ref() == "type")
+                            .filter(|_| self.config.list_type_markers)
+                            .map(|attr| ListMarker::from_type_attr(&attr.value))
+                            .unwrap_or(ListMarker::Decimal);
+                        let reversed = get_attr(attrs, "reversed").is_some();
+                        // A reversed list without a start counts down to 1; one whose start
+                        // is lower than its length stops at 0 (see `process_list`)
+                        let start = get_attr(attrs, "start")
+                            .and_then(|start| start.trim().parse::<u8>().ok())
+                            .unwrap_or_else(|| if reversed { count_children(handle, "li").min(u8::MAX as usize) as u8 } else { 1 });
+                        self.process_list(handle, ListType::Ordered(start, marker, reversed));
+                    }
+
+                    "blockquote" => self.process_blockquote(handle),
+
+                    "dl" => {
+                        self.add_double_newline();
+                        self.process_children(handle);
+                        self.add_double_newline();
+                    }
+
+                    // Pandoc-style definition lists: the term on its own line, then
+                    // one `: ` line per definition
+                    "dt" => {
+                        self.add_double_newline();
+                        let start = self.content.len();
+                        self.process_children(handle);
+                        let term = self.content.split_off(start);
+                        self.content.push_str(&WHITESPACE_REGEX.replace_all(term.trim(), " "));
+                        self.add_newline();
+                    }
+
+                    "dd" => {
+                        self.add_newline();
+                        let start = self.content.len();
+                        self.process_children(handle);
+                        let definition = self.content.split_off(start);
+                        let (first_line, rest) = definition.trim().split_once('\n').unwrap_or((definition.trim(), ""));
+                        self.content.push_str(": ");
+                        self.content.push_str(first_line);
+                        if !rest.is_empty() {
+                            self.content.push('\n');
+                            self.content.push_str(&indent_lines(rest, 2));
+                        }
+                        self.add_newline();
+                    }
+
+                    "q" => {
+                        let (open, close) = quote_marks(self.config.quote_locale.as_deref());
+                        self.content.push_str(open);
+                        self.process_children(handle);
+                        self.content.push_str(close);
+                    }
+
+                    "article" if self.config.article_separation == Some(ArticleSeparation::Rule) => {
+                        self.process_article(handle);
+                    }
+
+                    // Icon fonts draw their glyph from CSS, so there is nothing to convert
+                    "i" | "em" | "span" if is_icon_font(attrs, handle) => {}
+
+                    "span" | "font" if self.config.style_emphasis => self.process_styled_span(handle, attrs),
+
+                    tag if INLINE_TAGS.contains_key(tag) => {
+                        let (prefix, suffix) = INLINE_TAGS[tag];
+                        let start = self.content.len();
+                        self.process_children(handle);
+                        let inner = self.content.split_off(start);
+                        let text = inner.trim();
+                        // Only `*` emphasis is universally understood inside a heading line.
+                        let strip = self.in_heading && self.config.strip_heading_markers && !prefix.starts_with('*');
+                        if text.is_empty() || strip {
+                            self.content.push_str(&inner);
+                        } else {
+                            // Markers hugging whitespace (`== x ==`) aren't parsed as formatting,
+                            // so the whitespace moves outside them.
+                            self.content.push_str(&inner[..inner.len() - inner.trim_start().len()]);
+                            self.content.push_str(prefix);
+                            self.content.push_str(text);
+                            self.content.push_str(suffix);
+                            self.content.push_str(&inner[inner.trim_end().len()..]);
+                        }
+                    }
+
+                    tag if BLOCK_TAGS.contains_key(tag) => {
+                        if tag == "p" {
+                            self.record_summary(handle);
+                        }
+                        let start = self.content.len();
+                        self.add_double_newline();
+                        self.process_children(handle);
+                        if self.config.skip_empty_blocks && self.content[start..].trim().is_empty() {
+                            self.content.truncate(start);
+                        } else {
+                            self.add_double_newline();
+                        }
+                    }
+
+                    _ => {
+                        self.process_children(handle);
+                    }
+                }
+            }
+
+            NodeData::Text { contents } => {
+                let text = contents.borrow();
+                let mut processed_text = self.clean_text(&text);
+                // Code is already literal; in table cells only `|` is escaped, by format_table
+                if self.config.escape_markdown && !self.in_code_block && !self.in_table {
+                    processed_text = escape_markdown(&processed_text, self.content.chars().next_back());
+                }
+                self.content.push_str(&processed_text);
+            }
+
+            _ => self.process_children(handle),
+        }
+    }
+
+    /// Spans that fake `<b>`/`<i>` with inline `font-weight`/`font-style` get the
+    /// matching emphasis markers.
+    fn process_styled_span(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let style = get_attr(attrs, "style").unwrap_or_default().to_lowercase();
+        let (mut bold, mut italic) = (false, false);
+        for declaration in style.split(';') {
+            if let Some((property, value)) = declaration.split_once(':') {
+                let value = value.trim().trim_end_matches("!important").trim();
+                match property.trim() {
+                    "font-weight" => {
+                        bold = matches!(value, "bold" | "bolder") || value.parse::<u16>().is_ok_and(|weight| weight >= 600);
+                    }
+                    "font-style" => italic = matches!(value, "italic" | "oblique"),
+                    _ => {}
+                }
+            }
+        }
+
+        let marker = match (bold, italic) {
+            (true, true) => "***",
+            (true, false) => "**",
+            (false, true) => "*",
+            (false, false) => "",
+        };
+        if marker.is_empty() || self.in_code_block {
+            self.process_children(handle);
+            return;
+        }
+
+        let start = self.content.len();
+        self.process_children(handle);
+        let inner = self.content.split_off(start);
+        let text = inner.trim();
+        if text.is_empty() {
+            self.content.push_str(&inner);
+            return;
+        }
+
+        // Markers have to hug the text, so surrounding spaces move outside them
+        let leading = &inner[..inner.len() - inner.trim_start().len()];
+        let trailing = &inner[inner.trim_end().len()..];
+        self.content.push_str(&format!("{}{}{}{}{}", leading, marker, text, marker, trailing));
+    }
+
+    fn process_code_tag(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>, tag: &str) {
+        // Inside <pre> the fence already marks everything as code
+        if self.in_pre {
+            self.process_children(handle);
+            return;
+        }
+
+        let style = self.config.code_tag_styles.get(tag).copied().unwrap_or(CODE_TAGS[tag]);
+
+        let was_in_code = self.in_code_block;
+        self.in_code_block = !matches!(style, CodeStyle::Italic);
+        let start = self.content.len();
+        self.process_children(handle);
+        let code = self.content.split_off(start);
+        self.in_code_block = was_in_code;
+
+        match style {
+            CodeStyle::Backticks => {
+                // A classed multi-line <code> without a <pre> is still a code block
+                match language_from_class(attrs) {
+                    Some(lang) if code.trim().contains('\n') => {
+                        self.push_code_block(code.trim_matches('\n'), Some(&lang), None);
+                    }
+                    _ => self.content.push_str(&format!("`{}`", code)),
+                }
+            }
+            CodeStyle::Html => self.content.push_str(&format!("<{tag}>{}</{tag}>", escape_html(&code))),
+            CodeStyle::Italic => self.content.push_str(&format!("*{}*", code)),
+        }
+    }
+
+    /// A hard break in `hard_break_style` when `preserve_line_breaks` is set,
+    /// otherwise a single space.
+    fn process_line_break(&mut self) {
+        let marker = if self.config.cleaning_rules.preserve_line_breaks {
+            self.config.hard_break_style.marker()
+        } else {
+            " "
+        };
+
+        // With br_paragraphs, a second <br> straight after the first (only
+        // whitespace between) turns the pair into a paragraph break
+        if self.config.br_paragraphs {
+            if let Some(start) = self.last_break.take() {
+                let since = self.content.get(start..).unwrap_or_default();
+                if since.strip_prefix(marker).unwrap_or(since).trim().is_empty() {
+                    self.content.truncate(start);
+                    self.add_double_newline();
+                    return;
+                }
+            }
+            self.last_break = Some(self.content.len());
+        }
+
+        // The space isn't needed where whitespace is already written
+        if marker == " " && (self.content.is_empty() || self.content.ends_with(char::is_whitespace)) {
+            return;
+        }
+        self.content.push_str(marker);
+    }
+
+    /// Maps a detected language token to the fence info string, with
+    /// `language_aliases` taking precedence over the built-in table.
+    fn canonical_language(&self, language: &str) -> String {
+        if !self.config.canonical_languages {
+            return language.to_string();
+        }
+
+        let language = language.to_lowercase();
+        self.config.language_aliases.get(&language)
+            .cloned()
+            .or_else(|| LANGUAGE_ALIASES.get(language.as_str()).map(|canonical| canonical.to_string()))
+            .unwrap_or(language)
+    }
+
+    /// Fenced by default; language-less code can be written as an indented block
+    /// for renderers that prefer it.
+    /// `highlight` is a line range list like `4-6,9`, written into the info
+    /// string as `{4-6,9}` the way several renderers read it.
+    fn push_code_block(&mut self, code: &str, language: Option<&str>, highlight: Option<&str>) {
+        let language = language.map(|language| self.canonical_language(language));
+        let language = language.as_deref();
+        self.add_double_newline();
+
+        if language.is_none() && highlight.is_none() && self.config.indent_plain_code {
+            for line in code.trim_matches('\n').lines() {
+                if !line.is_empty() {
+                    self.content.push_str("    ");
+                }
+                self.content.push_str(line);
+                self.content.push('\n');
+            }
+        } else {
+            self.content.push_str("```");
+            self.content.push_str(language.unwrap_or_default());
+            if let Some(highlight) = highlight {
+                if language.is_some() {
+                    self.content.push(' ');
+                }
+                self.content.push_str(&format!("{{{}}}", highlight));
+            }
+            self.content.push('\n');
+            self.content.push_str(code);
+            self.content.push_str("\n```");
+        }
+
+        self.add_newline();
+    }
+
+    /// Maps a source heading level onto the next free depth, so `<h1>` followed by
+    /// `<h4>` comes out as `#` and `##`.
+    fn normalized_heading_level(&mut self, level: usize) -> usize {
+        while self.heading_stack.last().is_some_and(|open| *open >= level) {
+            self.heading_stack.pop();
+        }
+        self.heading_stack.push(level);
+        self.heading_stack.len()
+    }
+
+    fn process_header(&mut self, handle: &Handle, level: usize) {
+        self.add_double_newline();
+        self.content.push_str(&"#".repeat(level));
+        self.content.push(' ');
+        let start = self.content.len();
+        self.in_heading = true;
+        self.process_children(handle);
+        self.in_heading = false;
+        // A heading must stay on one line, so breaks and newlines inside it become spaces.
+        let text = self.content.split_off(start);
+        let text = WHITESPACE_REGEX.replace_all(text.trim(), " ");
+        if self.config.trim_heading_numbers {
+            self.content.push_str(&trim_heading(&text));
+        } else {
+            self.content.push_str(&text);
+        }
+        self.add_double_newline();
+    }
+
+    /// Emits a landmark's `aria-label` as a heading, unless the region already
+    /// has a heading of its own.
+    fn push_landmark_heading(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        const LEVEL: u8 = 2;
+
+        let Some(label) = get_attr(attrs, "aria-label")
+            .map(|label| WHITESPACE_REGEX.replace_all(label.trim(), " ").to_string())
+            .filter(|label| !label.is_empty())
+        else {
+            return;
+        };
+
+        let mut headings = Vec::new();
+        find_descendants(handle, &["h1", "h2", "h3", "h4", "h5", "h6"], &mut headings);
+        if !headings.is_empty() {
+            return;
+        }
+
+        self.headings.push((LEVEL, label.clone()));
+        if self.config.preserve_headings && LEVEL <= self.config.max_heading_level {
+            self.add_double_newline();
+            self.content.push_str(&format!("{} {}", "#".repeat(LEVEL as usize), label));
+            self.add_double_newline();
+        }
+    }
+
+    fn process_link(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let href = attrs.borrow()
+            .iter()
+            .find(|attr| attr.name.local.as_ref() == "href")
+            .map(|attr| attr.value.to_string());
+        let title = get_attr(attrs, "title").filter(|title| !title.trim().is_empty());
+
+        if !self.config.include_links {
+            self.process_children(handle);
+            if let Some(href) = &href {
+                self.record_link(handle, href);
+            }
+            return;
+        }
+
+        let old_content = self.content.clone();
+        self.content.clear();
+
+        self.process_children(handle);
+
+        let text = self.content.trim().to_string();
+        // Escaping could make the text differ from the URL it spells out
+        let raw_text = WHITESPACE_REGEX.replace_all(text_content(handle).trim(), " ").to_string();
+
+        self.content = old_content;
+
+        if let Some(href) = &href {
+            self.record_link(handle, href);
+        }
+
+        let wikilink = href.as_deref().and_then(|href| self.wikilink_target(href, true));
+        if let Some(target) = wikilink {
+            if text.is_empty() || text == target {
+                self.content.push_str(&format!("[[{}]]", target));
+            } else {
+                self.content.push_str(&format!("[[{}|{}]]", target, text));
+            }
+        } else if let Some(url) = href.map(|href| self.resolve_url(&href)) {
+            let truncated = self.config.resolve_truncated_links && is_truncated_url(&raw_text, &url);
+            if !text.is_empty() && raw_text != url && !truncated {
+                // Links that open a new tab lead off-site, so in print they become footnotes
+                let new_tab = get_attr(attrs, "target").is_some_and(|target| target.eq_ignore_ascii_case("_blank"));
+                match &title {
+                    _ if self.config.external_link_footnotes && new_tab => {
+                        let number = self.footnote_number(&url);
+                        self.content.push_str(&format!("{}[^{}]", text, number));
+                    }
+                    _ if self.config.link_format == LinkFormat::Reference => {
+                        let number = self.reference_number(&url, title);
+                        self.content.push_str(&format!("[{}][{}]", text, number));
+                    }
+                    Some(title) => self.content.push_str(&format!("[{}]({} \"{}\")", text, url, title.replace('"', "\\\""))),
+                    None => self.content.push_str(&format!("[{}]({})", text, url)),
+                }
+
+                if self.config.link_domain_badge {
+                    if let Some(host) = Url::parse(&url).ok().as_ref().and_then(Url::host_str) {
+                        self.content.push_str(&format!(" ({})", host.strip_prefix("www.").unwrap_or(host)));
+                    }
+                }
+            } else {
+                // Autolinks can't carry a title
+                self.content.push_str(&format!("<{}>", url));
+            }
+        }
+    }
+
+    /// Resolves `url` against the page URL, leaving it untouched when there is
+    /// no page URL or it can't be joined.
+    fn absolute_url(&self, url: &str) -> String {
+        let absolute = self.base_url.as_ref()
+            .and_then(|base| base.join(url).ok())
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| url.to_string());
+        self.clean_url(absolute)
+    }
+
+    /// Resolves a relative `href`/`src` for output. Absolute URLs (`data:` and
+    /// `mailto:` included) and in-page `#fragment`s are kept as written.
+    fn resolve_url(&self, url: &str) -> String {
+        if url.starts_with('#') {
+            return url.to_string();
+        }
+        if Url::parse(url).is_ok() {
+            return self.clean_url(url.to_string());
+        }
+        self.absolute_url(url)
+    }
+
+    fn clean_url(&self, url: String) -> String {
+        if self.config.strip_tracking_params {
+            strip_tracking_params(&url).unwrap_or(url)
+        } else {
+            url
+        }
+    }
+
+    fn record_link(&mut self, handle: &Handle, href: &str) {
+        if self.config.inventory {
+            let text = WHITESPACE_REGEX.replace_all(text_content(handle).trim(), " ").to_string();
+            let href = self.absolute_url(href);
+            self.links.push(LinkEntry { text, href });
+        }
+    }
+
+    /// The wikilink target for a same-site URL: the last path segment, with the
+    /// extension dropped for pages. `None` when wikilinks are off or the URL
+    /// points at another site.
+    fn wikilink_target(&self, href: &str, is_page: bool) -> Option<String> {
+        if self.config.link_format != LinkFormat::Wikilink {
+            return None;
+        }
+        if let Some(fragment) = href.strip_prefix('#').filter(|fragment| !fragment.is_empty()) {
+            return Some(format!("#{}", fragment));
+        }
+
+        let base = self.base_url.as_ref()?;
+        let url = base.join(href).ok()?;
+        if url.host_str() != base.host_str() || !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let segment = url.path_segments()
+            .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+            .unwrap_or_default();
+        let mut target = match segment.rsplit_once('.') {
+            Some((stem, _)) if is_page && !stem.is_empty() => stem.to_string(),
+            _ => segment.to_string(),
+        };
+        if target.is_empty() {
+            target = url.host_str()?.to_string();
+        }
+        if let Some(fragment) = url.fragment().filter(|fragment| is_page && !fragment.is_empty()) {
+            target.push('#');
+            target.push_str(fragment);
+        }
+        Some(target)
+    }
+
+    fn process_image(&mut self, _handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let attrs = attrs.borrow();
+        // Lazy loaders keep the real URL in a data attribute and a placeholder in `src`
+        let src = self.config.image_src_attributes.iter()
+            .map(String::as_str)
+            .chain(["src"])
+            .find_map(|wanted| attrs.iter()
+                .find(|attr| attr.name.local.as_ref() == wanted && !attr.value.trim().is_empty())
+                .map(|attr| attr.value.to_string()));
+
+        let alt = attrs.iter()
+            .find(|attr| attr.name.local.as_ref() == "alt")
+            .map(|attr| attr.value.to_string())
+            .unwrap_or_default();
+        let optional = |wanted: &str| attrs.iter()
+            .find(|attr| attr.name.local.as_ref() == wanted)
+            .map(|attr| attr.value.trim().to_string())
+            .filter(|value| !value.is_empty());
+        let (width, height, title) = (optional("width"), optional("height"), optional("title"));
+
+        // Emoji sets (Twemoji, WordPress smilies) render each emoji as an image
+        // with the character as its alt text
+        let emoji_class = optional("class").is_some_and(|class| class.split_whitespace()
+            .any(|class| class.contains("emoji") || class == "wp-smiley"));
+        let alt_text = alt.trim();
+        if self.config.emoji_as_text && !alt_text.is_empty() && (emoji_class || is_single_emoji(alt_text)) {
+            self.content.push_str(alt_text);
+            return;
+        }
+
+        if let Some(mut url) = src {
+            if self.config.strip_image_query && !url.starts_with("data:") {
+                url.truncate(url.find(['?', '#']).unwrap_or(url.len()));
+            }
+            if self.config.canonical_image_urls {
+                for (regex, replacement) in CDN_IMAGE_REWRITES.iter() {
+                    url = regex.replace(&url, *replacement).into_owned();
+                }
+            }
+            for (regex, replacement) in &self.image_rewrites {
+                url = regex.replace(&url, replacement.as_str()).into_owned();
+            }
+
+            if self.config.inventory {
+                self.images.push(ImageEntry { alt: alt.clone(), src: self.absolute_url(&url) });
+            }
+
+            self.add_newline();
+            match self.wikilink_target(&url, false) {
+                Some(target) if alt.is_empty() => self.content.push_str(&format!("![[{}]]", target)),
+                Some(target) => self.content.push_str(&format!("![[{}|{}]]", target, alt)),
+                // Markdown can't size an image, so sized ones stay HTML
+                None if width.is_some() || height.is_some() => {
+                    let mut tag = format!("<img src=\"{}\" alt=\"{}\"", escape_html(&self.resolve_url(&url)), escape_html(&alt));
+                    for (name, value) in [("width", &width), ("height", &height), ("title", &title)] {
+                        if let Some(value) = value {
+                            tag.push_str(&format!(" {}=\"{}\"", name, escape_html(value)));
+                        }
+                    }
+                    tag.push('>');
+                    self.content.push_str(&tag);
+                }
+                None => {
+                    let alt = escape_link_text(&alt);
+                    match &title {
+                        Some(title) => self.content.push_str(&format!("![{}]({} \"{}\")", alt, self.resolve_url(&url), title.replace('"', "\\\""))),
+                        None => self.content.push_str(&format!("![{}]({})", alt, self.resolve_url(&url))),
+                    }
+                }
+            }
+            self.add_newline();
+        }
+    }
+
+    /// Media players become a link to the first playable source, with a video's
+    /// poster frame kept as an image. Fallback text inside the element is dropped.
+    fn process_media(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>, kind: &str) {
+        let src = get_attr(attrs, "src").filter(|src| !src.trim().is_empty()).or_else(|| {
+            handle.children.borrow().iter()
+                .filter(|child| element_name(child).as_deref() == Some("source"))
+                .find_map(|source| match &source.data {
+                    NodeData::Element { attrs, .. } => get_attr(attrs, "src").filter(|src| !src.trim().is_empty()),
+                    _ => None,
+                })
+        });
+        let poster = get_attr(attrs, "poster").filter(|poster| kind == "video" && !poster.trim().is_empty());
+        let label = get_attr(attrs, "title")
+            .unwrap_or_else(|| if kind == "video" { "Video".to_string() } else { "Audio".to_string() });
+
+        if src.is_none() && poster.is_none() {
+            return;
+        }
+
+        self.add_double_newline();
+        if let Some(poster) = poster {
+            self.content.push_str(&format!("![{}]({})", label, self.resolve_url(&poster)));
+            self.add_newline();
+        }
+        if let Some(src) = src {
+            self.content.push_str(&format!("[{}]({})", label, self.resolve_url(&src)));
+        }
+        self.add_double_newline();
+    }
+
+    /// Puts a rule before every top-level article but the first; articles
+    /// nested inside one (comments, embeds) stay part of it.
+    fn process_article(&mut self, handle: &Handle) {
+        if self.article_depth == 0 {
+            if self.articles_seen > 0 {
+                self.add_double_newline();
+                self.content.push_str("---");
+            }
+            self.articles_seen += 1;
+            // Each article starts its own heading hierarchy
+            self.heading_stack.clear();
+        }
+
+        self.article_depth += 1;
+        self.add_double_newline();
+        self.process_children(handle);
+        self.add_double_newline();
+        self.article_depth -= 1;
+    }
+
+    /// Marks every line the children emit with one `QUOTE_LEVEL` per enclosing
+    /// quote; the outermost quote then turns the marks into `>` prefixes, so a
+    /// quoted code line that itself starts with `>` isn't mistaken for nesting.
+    fn process_blockquote(&mut self, handle: &Handle) {
+        self.add_double_newline();
+
+        let start = self.content.len();
+        self.blockquote_depth += 1;
+        self.process_children(handle);
+        self.blockquote_depth -= 1;
+        let quoted = self.content.split_off(start);
+
+        for line in quoted.trim_matches('\n').lines() {
+            self.content.push(QUOTE_LEVEL);
+            self.content.push_str(line);
+            self.content.push('\n');
+        }
+
+        if self.blockquote_depth == 0 {
+            let marked = self.content.split_off(start);
+            self.content.push_str(&render_quote_levels(&marked));
+        }
+
+        self.add_double_newline();
+    }
+
+    /// Markdown has no collapsible syntax, so `<details>` stays an HTML block with a
+    /// markdown body (blank lines around it so renderers parse the inside).
+    fn process_details(&mut self, handle: &Handle) {
+        let summary = find_child(handle, &["summary"])
+            .map(|summary| WHITESPACE_REGEX.replace_all(text_content(&summary).trim(), " ").to_string())
+            .or_else(|| self.config.default_summary.clone());
+
+        self.add_double_newline();
+        self.content.push_str("<details>\n");
+        if let Some(summary) = summary {
+            self.content.push_str(&format!("<summary>{}</summary>", escape_html(&summary)));
+        }
+        self.add_double_newline();
+
+        for child in handle.children.borrow().iter() {
+            if element_name(child).as_deref() != Some("summary") {
+                self.process_node(child);
+            }
+        }
+
+        self.add_double_newline();
+        self.content.push_str("</details>");
+        self.add_double_newline();
+    }
+
+    fn process_list(&mut self, handle: &Handle, list_type: ListType) {
+        self.list_type_stack.push(list_type);
+
+        let mut current_count = match list_type {
+            ListType::Ordered(start, _, _) => u32::from(start),
+            _ => 1,
+        };
+
+        // A sublist starts on its own line, under its parent item's text
+        self.add_newline();
+
+        for child in handle.children.borrow().iter() {
+            if let NodeData::Element { ref name, ref attrs, .. } = child.data {
+                if name.local.as_ref() == "li" {
+                    if self.config.skip_emoji_items && is_icon_item(child) {
+                        continue;
+                    }
+                    // `<li value>` renumbers this item and the ones after it
+                    if let Some(value) = get_attr(attrs, "value").and_then(|value| value.trim().parse().ok()) {
+                        current_count = value;
+                    }
+                    let prefix = match list_type {
+                        ListType::Unordered => "* ".to_string(),
+                        ListType::Ordered(_, marker, _) => {
+                            format!("{}{} ", marker.format(current_count), self.config.ordered_delimiter.as_str())
+                        }
+                    };
+
+                    let start = self.content.len();
+                    self.process_node(child);
+                    let item = self.content.split_off(start);
+
+                    // Everything after the first line (sublists, paragraphs, code)
+                    // lines up with the item's text
+                    let (first_line, rest) = item.trim().split_once('\n').unwrap_or((item.trim(), ""));
+                    self.content.push_str(&prefix);
+                    self.content.push_str(first_line);
+                    if !rest.is_empty() {
+                        self.content.push('\n');
+                        self.content.push_str(&indent_lines(rest, prefix.len()));
+                    }
+
+                    match list_type {
+                        // Lettered and roman items stand as separate paragraphs
+                        ListType::Ordered(_, marker, _) if marker != ListMarker::Decimal => self.add_double_newline(),
+                        _ => self.add_newline(),
+                    }
+                    if matches!(list_type, ListType::Ordered(_, _, true)) {
+                        // Markdown list markers can't be negative (`-1.` isn't a list item),
+                        // so a countdown from a low start stops at 0 and repeats it
+                        current_count = current_count.saturating_sub(1);
+                    } else {
+                        current_count += 1;
+                    }
+                }
+            }
+        }
+
+        self.list_type_stack.pop();
+        self.add_newline();
+    }
+
+    fn record_abbreviation(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let expansion = attrs.borrow().iter()
+            .find(|attr| attr.name.local.as_ref() == "title")
+            .map(|attr| attr.value.trim().to_string())
+            .unwrap_or_default();
+        let abbreviation = text_content(handle).trim().to_string();
+
+        if abbreviation.is_empty() || expansion.is_empty() {
+            return;
+        }
+
+        if !self.abbreviations.iter().any(|(known, _)| *known == abbreviation) {
+            self.abbreviations.push((abbreviation, expansion));
+        }
+    }
+
+    /// Keeps the opening words of the first paragraph long enough to stand in
+    /// for a missing description.
+    fn record_summary(&mut self, handle: &Handle) {
+        let Some(max_words) = self.config.summary_words else {
+            return;
+        };
+        if self.metadata.summary.is_some() || self.metadata.description.is_some() {
+            return;
+        }
+
+        let text = text_content(handle);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if words.len() < SUMMARY_MIN_WORDS {
+            return;
+        }
+
+        let mut summary = words[..words.len().min(max_words)].join(" ");
+        if words.len() > max_words {
+            summary.push('…');
+        }
+        self.metadata.summary = Some(summary);
+    }
+
+    /// Numbers a reference definition, reusing the number of an identical earlier one.
+    fn reference_number(&mut self, url: &str, title: Option<String>) -> usize {
+        let reference = (url.to_string(), title);
+        match self.references.iter().position(|known| *known == reference) {
+            Some(index) => index + 1,
+            None => {
+                self.references.push(reference);
+                self.references.len()
+            }
+        }
+    }
+
+    fn footnote_number(&mut self, url: &str) -> usize {
+        match self.footnotes.iter().position(|known| known == url) {
+            Some(index) => index + 1,
+            None => {
+                self.footnotes.push(url.to_string());
+                self.footnotes.len()
+            }
+        }
+    }
+
+    fn format_references(&self) -> String {
+        let definition = |number: usize, (url, title): &(String, Option<String>)| match title {
+            Some(title) => format!("[{}]: {} \"{}\"\n", number, url, title.replace('"', "\\\"")),
+            None => format!("[{}]: {}\n", number, url),
+        };
+
+        if !self.config.group_references_by_domain {
+            return self.references.iter().enumerate()
+                .map(|(index, reference)| definition(index + 1, reference))
+                .collect();
+        }
+
+        // Hostless URLs (relative, mailto:) sort after every domain
+        let mut groups: BTreeMap<(bool, String), Vec<usize>> = BTreeMap::new();
+        for (index, (url, _)) in self.references.iter().enumerate() {
+            let host = Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_string));
+            groups.entry((host.is_none(), host.unwrap_or_default())).or_default().push(index);
+        }
+
+        groups.into_iter()
+            .map(|((_, host), indices)| {
+                let mut group = format!("<!-- {} -->\n", if host.is_empty() { "other" } else { &host });
+                for index in indices {
+                    group.push_str(&definition(index + 1, &self.references[index]));
+                }
+                group
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn format_glossary(&self) -> String {
+        let mut glossary = String::from("## Glossary\n\n");
+        for (abbreviation, expansion) in &self.abbreviations {
+            glossary.push_str(&format!("* **{}**: {}\n", abbreviation, expansion));
+        }
+        glossary
+    }
+
+    fn collect_metadata(&mut self, handle: &Handle) {
+        if let NodeData::Element { name, attrs, .. } = &handle.data {
+            if name.local.as_ref() == "meta" {
+                self.extract_metadata(handle, attrs);
+            }
+            // Read from the tree before cleaning, so `remove_scripts` doesn't hide it
+            if name.local.as_ref() == "script"
+                && get_attr(attrs, "type").is_some_and(|kind| kind.trim().eq_ignore_ascii_case("application/ld+json"))
+            {
+                self.metadata.linked_data.read(&text_content(handle));
+            }
+            // SVG has a <title> element of its own
+            if name.local.as_ref() == "title" && &*name.ns == "http://www.w3.org/1999/xhtml" && self.metadata.document_title.is_none() {
+                let text = WHITESPACE_REGEX.replace_all(text_content(handle).trim(), " ").to_string();
+                if !text.is_empty() {
+                    self.metadata.document_title = Some(text);
+                }
+            }
+            if name.local.as_ref() == "a" && self.metadata.author_link.is_none()
+                && get_attr(attrs, "rel").is_some_and(|rel| rel.split_whitespace().any(|token| token.eq_ignore_ascii_case("author")))
+            {
+                let text = WHITESPACE_REGEX.replace_all(text_content(handle).trim(), " ").to_string();
+                if !text.is_empty() {
+                    self.metadata.author_link = Some(text);
+                }
+            }
+            if self.config.include_contact {
+                self.extract_contact(handle, attrs);
+            }
+            // `<link rel="next">` in the head, or a pager's `<a rel="next">`
+            if self.config.include_pagination && matches!(name.local.as_ref(), "link" | "a") {
+                let rel = get_attr(attrs, "rel").unwrap_or_default().to_ascii_lowercase();
+                if let Some(href) = get_attr(attrs, "href").filter(|href| !href.trim().is_empty()) {
+                    let url = self.absolute_url(href.trim());
+                    for token in rel.split_whitespace() {
+                        match token {
+                            "next" if self.metadata.next_page.is_none() => self.metadata.next_page = Some(url.clone()),
+                            "prev" | "previous" if self.metadata.prev_page.is_none() => self.metadata.prev_page = Some(url.clone()),
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            if self.config.include_breadcrumbs && self.metadata.breadcrumbs.is_empty() {
+                if let Some(trail) = breadcrumb_trail(handle, attrs) {
+                    self.metadata.breadcrumbs = trail;
+                }
+            }
+        }
+
+        for child in handle.children.borrow().iter() {
+            self.collect_metadata(child);
+        }
+    }
+
+    /// Email, phone and postal address from an `<address>` block or a schema.org
+    /// `Person`/`Organization`. The first value found for each field wins.
+    fn extract_contact(&mut self, handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let is_address = element_name(handle).as_deref() == Some("address");
+        let is_schema = get_attr(attrs, "itemtype").is_some_and(|itemtype| {
+            ["/Person", "/Organization", "/LocalBusiness"].iter().any(|kind| itemtype.ends_with(kind))
+        });
+        if !is_address && !is_schema {
+            return;
+        }
+
+        let mut links = Vec::new();
+        find_descendants(handle, &["a"], &mut links);
+        for link in &links {
+            let NodeData::Element { attrs, .. } = &link.data else { continue };
+            let Some(href) = get_attr(attrs, "href") else { continue };
+            // Drop `?subject=...` and similar from the target
+            let target = |scheme: &str| href.strip_prefix(scheme)
+                .map(|value| value.split('?').next().unwrap_or_default().trim().to_string())
+                .filter(|value| !value.is_empty());
+            if let Some(email) = target("mailto:") {
+                self.metadata.email.get_or_insert(email);
+            } else if let Some(phone) = target("tel:") {
+                self.metadata.phone.get_or_insert(phone);
+            }
+        }
+
+        let collapsed = |node: &Handle| match &node.data {
+            NodeData::Element { attrs, .. } if element_name(node).as_deref() == Some("meta") => get_attr(attrs, "content"),
+            _ => Some(WHITESPACE_REGEX.replace_all(text_content(node).trim(), " ").to_string()),
+        }.filter(|value| !value.is_empty());
+
+        if is_schema {
+            for (property, field) in [("email", 0), ("telephone", 1), ("address", 2)] {
+                let mut found = Vec::new();
+                find_descendants_with_attr(handle, "itemprop", property, &mut found);
+                if let Some(value) = found.first().and_then(collapsed) {
+                    let value = value.strip_prefix("mailto:").unwrap_or(&value).to_string();
+                    match field {
+                        0 => self.metadata.email.get_or_insert(value),
+                        1 => self.metadata.phone.get_or_insert(value),
+                        _ => self.metadata.address.get_or_insert(value),
+                    };
+                }
+            }
+        } else {
+            let postal = postal_lines(handle).join(", ");
+            if !postal.is_empty() {
+                self.metadata.address.get_or_insert(postal);
+            }
+        }
+    }
+
+    fn extract_metadata(&mut self, _handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) {
+        let attrs = attrs.borrow();
+
+        if let Some(property) = attrs.iter().find(|attr| attr.name.local.as_ref() == "property") {
+            if let Some(content) = attrs.iter().find(|attr| attr.name.local.as_ref() == "content") {
+                match property.value.as_ref() {
+                    "og:title" => self.metadata.title = Some(content.value.to_string()),
+                    "og:description" => self.metadata.description = Some(content.value.to_string()),
+                    "article:author" => self.metadata.author = Some(content.value.to_string()),
+                    "article:published_time" => self.metadata.date = Some(content.value.to_string()),
+                    "article:tag" => self.metadata.tags.push(content.value.to_string()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn process_children(&mut self, handle: &Handle) {
+        for child in handle.children.borrow().iter() {
+            self.process_node(child);
+        }
+    }
+
+    fn add_newline(&mut self) {
+        if !self.content.ends_with('\n') {
+            self.content.push('\n');
+        }
+    }
+
+    fn add_double_newline(&mut self) {
+        self.add_newline();
+        if !self.content.ends_with("\n\n") {
+            self.content.push('\n');
+        }
+    }
+
+    fn format_table(&mut self) {
+        if self.table_rows.is_empty() {
+            return;
+        }
+
+        if let Some(max_columns) = self.config.max_table_columns.filter(|max| *max > 0) {
+            if self.table_rows.iter().any(|row| row.len() > max_columns) {
+                for row in &mut self.table_rows {
+                    row.resize(max_columns, String::new());
+                    row.push("…".to_string());
+                }
+            }
+        }
+
+        // An unescaped pipe would end the cell early and shift the rest of the row
+        for cell in self.table_rows.iter_mut().flatten() {
+            if cell.contains('|') {
+                *cell = cell.replace('|', "\\|");
+            }
+        }
+
+        let col_count = self.table_rows.iter().map(Vec::len).max().unwrap_or_default();
+        let mut col_widths = vec![0; col_count];
+
+        for row in &self.table_rows {
+            for (i, cell) in row.iter().enumerate() {
+                if i < col_count {
+                    col_widths[i] = col_widths[i].max(cell.chars().count());
+                }
+            }
+        }
+        // `:-:` is the narrowest alignment rule, and the rows have to be as wide
+        for (i, width) in col_widths.iter_mut().enumerate() {
+            if self.table_alignments.get(i).copied().flatten().is_some() {
+                *width = (*width).max(3);
+            }
+        }
+
+        self.add_double_newline();
+
+        let rows_to_process = self.table_rows.clone();
+
+        // Markdown tables always start with a header, so a table without one
+        // gets an empty header row instead of promoting its first data row
+        let (header_row, body_rows) = if self.table_header_seen {
+            (rows_to_process[0].clone(), &rows_to_process[1..])
+        } else {
+            (vec![String::new(); col_count], &rows_to_process[..])
+        };
+
+        self.format_table_row(&header_row, &col_widths);
+        self.content.push('|');
+        for (i, width) in col_widths.iter().enumerate() {
+            let rule = match self.table_alignments.get(i).copied().flatten() {
+                None => "-".repeat((*width).max(1)),
+                Some(Alignment::Left) => format!(":{}", "-".repeat(width.saturating_sub(1).max(1))),
+                Some(Alignment::Right) => format!("{}:", "-".repeat(width.saturating_sub(1).max(1))),
+                Some(Alignment::Center) => format!(":{}:", "-".repeat(width.saturating_sub(2).max(1))),
+            };
+            self.content.push_str(&format!(" {} |", rule));
+        }
+        self.add_newline();
+
+        for row in body_rows {
+            self.format_table_row(row, &col_widths);
+        }
+
+        self.add_newline();
+    }
+
+    fn format_table_row(&mut self, row: &[String], col_widths: &[usize]) {
+        self.content.push('|');
+        for (i, cell) in row.iter().enumerate() {
+            if i < col_widths.len() {
+                let padding = " ".repeat(col_widths[i].saturating_sub(cell.chars().count()));
+                self.content.push_str(&format!(" {}{} |", cell, padding));
+            }
+        }
+        self.add_newline();
+    }
+
+    /// Locates each recorded element's output in the final markdown. Output is
+    /// matched by its first line, so entries stay in place when later passes
+    /// prefix lines (blockquotes) or move them (metadata, whitespace cleaning).
+    #[cfg(feature = "worker")]
+    pub(crate) fn source_map(&self, markdown: &str) -> Vec<SourceMapEntry> {
+        let lines: Vec<&str> = markdown.lines().collect();
+        let mut provenance: Vec<&Provenance> = self.provenance.iter().collect();
+        provenance.sort_by_key(|entry| entry.order);
+
+        let mut entries = Vec::new();
+        let mut cursor = 0;
+        for entry in provenance {
+            let first_line = entry.emitted.lines().next().unwrap_or_default().trim();
+            let Some(found) = lines.iter().skip(cursor).position(|line| line.contains(first_line)) else {
+                continue;
+            };
+            let start_line = cursor + found;
+            let end_line = (start_line + entry.emitted.lines().count() - 1).min(lines.len() - 1);
+            entries.push(SourceMapEntry {
+                start_line: start_line + 1,
+                end_line: end_line + 1,
+                tag: entry.tag.clone(),
+                path: entry.path.clone(),
+            });
+            // Children start on their parent's first line at the earliest
+            cursor = start_line;
+        }
+        entries
+    }
+
+    pub(crate) fn body_is_empty(&self) -> bool {
+        self.content.trim().is_empty()
+    }
+
+    pub(crate) fn result(&self) -> String {
+        let rendered = self.render();
+
+        // Never emit a byte order mark, even when the source page starts with one
+        let mut output = rendered
+            .trim_start_matches(|c: char| c == '\u{feff}' || c.is_whitespace())
+            .to_string();
+
+        for (regex, replacement) in &self.replacements {
+            output = regex.replace_all(&output, replacement.as_str()).into_owned();
+        }
+
+        if self.config.final_newline && !output.is_empty() {
+            output.push('\n');
+        }
+        output
+    }
+
+    fn render(&self) -> String {
+        // Metadata-only pages (redirect stubs, index pages) still get their front matter
+        if self.config.include_metadata && self.body_is_empty() {
+            return self.metadata.format_metadata(&self.config).trim().to_string();
+        }
+
+        let mut final_content = String::with_capacity(self.content.len() + 1000);
+
+        if self.config.include_metadata {
+            final_content.push_str(&self.metadata.format_metadata(&self.config));
+        }
+
+        match self.config.max_sections.filter(|max| *max > 0) {
+            Some(max) => final_content.push_str(truncate_sections(self.content.trim(), max).trim()),
+            None => final_content.push_str(self.content.trim()),
+        }
+
+        if !self.abbreviations.is_empty() {
+            final_content.push_str("\n\n");
+            final_content.push_str(&self.format_glossary());
+        }
+
+        if !self.references.is_empty() {
+            final_content.push_str("\n\n");
+            final_content.push_str(&self.format_references());
+        }
+
+        if !self.footnotes.is_empty() {
+            final_content.push_str("\n\n");
+            for (index, url) in self.footnotes.iter().enumerate() {
+                final_content.push_str(&format!("[^{}]: {}\n", index + 1, url));
+            }
+        }
+
+        match self.config.whitespace_mode() {
+            WhitespaceMode::Aggressive if !self.config.cleaning_rules.preserve_line_breaks => {
+                let cleaned = collapse_whitespace_outside_code(&final_content, &WHITESPACE_REGEX, "\n\n");
+                cleaned.trim().to_string()
+            }
+            WhitespaceMode::Inline => {
+                let cleaned = collapse_whitespace_outside_code(&final_content, &BLANK_LINES_REGEX, "\n\n");
+                cleaned.trim().to_string()
+            }
+            _ => final_content.trim().to_string(),
+        }
+    }
+
+    #[cfg(feature = "worker")]
+    pub(crate) fn outline(&self) -> Vec<OutlineNode> {
+        let mut roots = Vec::new();
+        let mut open: Vec<OutlineNode> = Vec::new();
+
+        for (level, text) in &self.headings {
+            while open.last().is_some_and(|node| node.level >= *level) {
+                let node = open.pop().unwrap();
+                attach_outline_node(&mut open, &mut roots, node);
+            }
+            open.push(OutlineNode { level: *level, text: text.clone(), children: Vec::new() });
+        }
+
+        while let Some(node) = open.pop() {
+            attach_outline_node(&mut open, &mut roots, node);
+        }
+
+        roots
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct LinkEntry {
+    text: String,
+    href: String,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ImageEntry {
+    alt: String,
+    src: String,
+}
+
+/// One element's span of output lines (1-based, inclusive) for `source_map`.
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct SourceMapEntry {
+    start_line: usize,
+    end_line: usize,
+    tag: String,
+    path: String,
+}
+
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct SourceMapped {
+    pub(crate) markdown: String,
+    pub(crate) lines: Vec<SourceMapEntry>,
+    pub(crate) page: PageInfo,
+}
+
+/// The `inventory` response: the markdown plus every link and image met during the walk.
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct Inventory {
+    pub(crate) markdown: String,
+    pub(crate) links: Vec<LinkEntry>,
+    pub(crate) images: Vec<ImageEntry>,
+    pub(crate) page: PageInfo,
+}
+
+/// How the page was served, for telling a mis-declared or non-UTF-8 page
+/// apart from a conversion bug when the text comes out garbled.
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct PageInfo {
+    pub(crate) content_type: String,
+    pub(crate) charset: Option<String>,
+}
+
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct OutlineNode {
+    level: u8,
+    text: String,
+    children: Vec<OutlineNode>,
+}
+
+#[cfg(feature = "worker")]
+#[derive(Debug, Serialize)]
+pub(crate) struct Section {
+    heading: String,
+    level: u8,
+    content: String,
+}
+
+/// Splits converted markdown at every heading of `max_level` or shallower. Text
+/// before the first such heading becomes a level-0 section with an empty heading.
+#[cfg(feature = "worker")]
+pub(crate) fn split_sections(markdown: &str, max_level: u8) -> Vec<Section> {
+    let mut sections = Vec::new();
+    let mut current = Section { heading: String::new(), level: 0, content: String::new() };
+    let mut in_fence = false;
+
+    for line in markdown.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+
+        let heading = HEADING_LINE_REGEX.captures(line)
+            .filter(|_| !in_fence)
+            .map(|caps| (caps[1].len() as u8, caps[2].trim().to_string()))
+            .filter(|(level, _)| *level <= max_level);
+
+        if let Some((level, text)) = heading {
+            let finished = std::mem::replace(&mut current, Section { heading: text, level, content: String::new() });
+            if finished.level > 0 || !finished.content.trim().is_empty() {
+                sections.push(finished);
+            }
+        } else {
+            current.content.push_str(line);
+            current.content.push('\n');
+        }
+    }
+
+    sections.push(current);
+
+    for section in &mut sections {
+        section.content = section.content.trim().to_string();
+    }
+    sections
+}
+
+/// Cuts markdown off before its `max + 1`th top-level section, where top-level is
+/// the shallowest heading level present, and notes how much was left out.
+fn truncate_sections(markdown: &str, max: usize) -> String {
+    let mut headings = Vec::new();
+    let mut in_fence = false;
+    let mut offset = 0;
+
+    for line in markdown.split_inclusive('\n') {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+        }
+        if let Some(caps) = HEADING_LINE_REGEX.captures(line.trim_end()).filter(|_| !in_fence) {
+            headings.push((caps[1].len(), offset));
+        }
+        offset += line.len();
+    }
+
+    let Some(top_level) = headings.iter().map(|(level, _)| *level).min() else {
+        return markdown.to_string();
+    };
+    let starts: Vec<usize> = headings.iter()
+        .filter(|(level, _)| *level == top_level)
+        .map(|(_, offset)| *offset)
+        .collect();
+
+    match starts.get(max) {
+        Some(&cut) => format!(
+            "{}\n\n*Truncated: showing the first {} of {} sections.*",
+            markdown[..cut].trim_end(),
+            max,
+            starts.len()
+        ),
+        None => markdown.to_string(),
+    }
+}
+
+#[cfg(feature = "worker")]
+fn attach_outline_node(open: &mut [OutlineNode], roots: &mut Vec<OutlineNode>, node: OutlineNode) {
+    match open.last_mut() {
+        Some(parent) => parent.children.push(node),
+        None => roots.push(node),
+    }
+}
+
+/// Runs `pattern` over everything except code blocks, which are copied through
+/// untouched so indentation and blank lines inside code survive. Indented lines
+/// only count as code after a blank line, as in CommonMark.
+fn collapse_whitespace_outside_code(text: &str, pattern: &Regex, replacement: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut prose = String::new();
+    let mut in_fence = false;
+    let mut in_indented = false;
+    let mut after_blank = true;
+
+    for line in text.split_inclusive('\n') {
+        let is_fence = line.trim_start().starts_with("```");
+        let is_blank = line.trim().is_empty();
+        let is_indented = line.starts_with("    ") || line.starts_with('\t');
+
+        if in_fence {
+            output.push_str(line);
+            in_fence = !is_fence;
+        } else if is_fence || (is_indented && !is_blank && (after_blank || in_indented)) {
+            output.push_str(&pattern.replace_all(&prose, replacement));
+            prose.clear();
+            output.push_str(line);
+            in_fence = is_fence;
+            in_indented = !is_fence;
+        } else if in_indented && is_blank {
+            output.push_str(line);
+        } else {
+            in_indented = false;
+            prose.push_str(line);
+        }
+
+        after_blank = is_blank;
+    }
+
+    output.push_str(&pattern.replace_all(&prose, replacement));
+    output
+}
+
+/// Parses `html` and picks the node conversion starts from.
+fn parse_root(html: &str, config: &ConvertConfig) -> (RcDom, Handle) {
+    let dom = parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut html.as_bytes())
+        .unwrap();
+
+    // The first selector that matches becomes the conversion root
+    let root = config.content_selectors.iter()
+        .find_map(|selector| find_element(&dom.document, selector))
+        .unwrap_or_else(|| dom.document.clone());
+
+    if config.collapse_wrappers {
+        collapse_wrappers(&root);
+    }
+
+    (dom, root)
+}
+
+pub(crate) fn convert_document(html: &str, config: ConvertConfig, base_url: Option<&str>) -> MarkdownFormatter {
+    let (dom, root) = parse_root(html, &config);
+
+    let mut formatter = MarkdownFormatter::new(config);
+    formatter.base_url = base_url.and_then(|url| Url::parse(url).ok());
+    if formatter.config.include_metadata {
+        formatter.collect_metadata(&dom.document);
+        formatter.metadata.apply_fallbacks();
+    }
+    formatter.process_node(&root);
+    formatter
+}
+
+/// Converts an HTML document to markdown. `base_url` is the page's own URL, used
+/// to resolve relative links and images.
+pub fn html_to_markdown(html: &str, config: ConvertConfig, base_url: Option<&str>) -> String {
+    convert_document(html, config, base_url).result()
+}
+
+/// Converts each top-level `<article>` on its own, or the whole page when it
+/// has none. Page metadata isn't repeated per article.
+#[cfg(feature = "worker")]
+pub(crate) fn convert_articles(html: &str, config: ConvertConfig, base_url: Option<&str>) -> Vec<String> {
+    let (_dom, root) = parse_root(html, &config);
+
+    let mut articles = Vec::new();
+    find_descendants(&root, &["article"], &mut articles);
+    if articles.is_empty() {
+        articles.push(root);
+    }
+
+    let config = ConvertConfig { include_metadata: false, ..config };
+    articles.iter()
+        .map(|article| {
+            let mut formatter = MarkdownFormatter::new(config.clone());
+            formatter.base_url = base_url.and_then(|url| Url::parse(url).ok());
+            formatter.process_node(article);
+            formatter.result()
+        })
+        .collect()
+}
+
+fn get_attr(attrs: &RefCell<Vec<html5ever::Attribute>>, name: &str) -> Option<String> {
+    attrs.borrow().iter()
+        .find(|attr| attr.name.local.as_ref() == name)
+        .map(|attr| attr.value.to_string())
+}
+
+fn language_from_class(attrs: &RefCell<Vec<html5ever::Attribute>>) -> Option<String> {
+    attrs.borrow().iter()
+        .find(|attr| attr.name.local.as_ref() == "class")
+        .and_then(|attr| {
+            attr.value.split_whitespace()
+                .find_map(|class| ["language-", "lang-", "highlight-source-"].iter()
+                    .find_map(|prefix| class.strip_prefix(prefix))
+                    .filter(|language| !language.is_empty()))
+                .map(str::to_string)
+        })
+}
+
+/// Drops `utm_*`, click IDs and similar referral parameters, keeping the rest in
+/// order. `None` when the URL doesn't parse or carries none of them.
+fn strip_tracking_params(url: &str) -> Option<String> {
+    const TRACKING_PREFIXES: &[&str] = &[
+        "utm_", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid",
+        "igshid", "mc_cid", "mc_eid", "_hsenc", "_hsmi", "mkt_tok",
+    ];
+
+    let mut parsed = Url::parse(url).ok()?;
+    // Kept parameters are copied as raw segments; re-serializing them would
+    // re-encode values (`a%20b` to `a+b`, `flag` to `flag=`)
+    let segments: Vec<&str> = parsed.query()?.split('&').collect();
+    let kept: Vec<&str> = segments.iter().copied()
+        .filter(|segment| {
+            let key = url::form_urlencoded::parse(segment.as_bytes()).next().map(|(key, _)| key).unwrap_or_default();
+            !TRACKING_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+        })
+        .collect();
+    if kept.len() == segments.len() {
+        return None;
+    }
+
+    let query = kept.join("&");
+    parsed.set_query((!query.is_empty()).then_some(query.as_str()));
+    Some(parsed.to_string())
+}
+
+/// True when link text is the href cut short, like `example.com/very-lo…` for
+/// `https://www.example.com/very-long-path`. The text must cover at least the host.
+fn is_truncated_url(text: &str, url: &str) -> bool {
+    fn strip_prefixes(value: &str) -> &str {
+        let value = value.strip_prefix("https://")
+            .or_else(|| value.strip_prefix("http://"))
+            .unwrap_or(value);
+        value.strip_prefix("www.").unwrap_or(value)
+    }
+
+    let stem = strip_prefixes(text.trim().trim_end_matches(['…', '.']).trim_end());
+    let target = strip_prefixes(url.trim());
+    let host_len = target.find('/').unwrap_or(target.len());
+
+    !stem.is_empty() && stem.len() >= host_len && target.starts_with(stem)
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The `data-line`/`data-highlight` range list, normalized to `4-6,9`. Values
+/// that aren't plain line numbers and ranges are ignored.
+fn highlighted_lines(attrs: &RefCell<Vec<html5ever::Attribute>>) -> Option<String> {
+    let value = get_attr(attrs, "data-line").or_else(|| get_attr(attrs, "data-highlight"))?;
+    let ranges: Vec<&str> = value.split(',').map(str::trim).filter(|range| !range.is_empty()).collect();
+    let valid = !ranges.is_empty() && ranges.iter().all(|range| {
+        range.split('-').map(str::trim).all(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+    });
+    valid.then(|| ranges.iter().map(|range| range.replace(' ', "")).collect::<Vec<_>>().join(","))
+}
+
+/// A cell's alignment from its `align` attribute or an inline `text-align`.
+fn cell_alignment(attrs: &RefCell<Vec<html5ever::Attribute>>) -> Option<Alignment> {
+    let from_style = get_attr(attrs, "style").and_then(|style| {
+        // The last declaration wins, as in CSS
+        style.rsplit(';')
+            .filter_map(|declaration| declaration.split_once(':'))
+            .find(|(property, _)| property.trim().eq_ignore_ascii_case("text-align"))
+            .map(|(_, value)| value.trim().to_lowercase())
+    });
+
+    match from_style.or_else(|| get_attr(attrs, "align").map(|align| align.trim().to_lowercase())).as_deref() {
+        Some("left" | "start") => Some(Alignment::Left),
+        Some("center") => Some(Alignment::Center),
+        Some("right" | "end") => Some(Alignment::Right),
+        _ => None,
+    }
+}
+
+/// Drops a leading section number (`1.`, `2.3`, `IV.`) and trailing colons from
+/// heading text, keeping the original if nothing would be left.
+fn trim_heading(text: &str) -> String {
+    let trimmed = HEADING_NUMBER_REGEX.replace(text, "");
+    let trimmed = trimmed.trim_end().trim_end_matches([':', '：']).trim_end();
+    if trimmed.is_empty() {
+        text.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Opening and closing marks for `<q>` by language tag (`fr`, `de-CH`, ...),
+/// falling back to ASCII double quotes.
+fn quote_marks(locale: Option<&str>) -> (&'static str, &'static str) {
+    let language = locale
+        .and_then(|locale| locale.split(['-', '_']).next())
+        .map(str::to_lowercase)
+        .unwrap_or_default();
+
+    match language.as_str() {
+        "fr" => ("« ", " »"),
+        "es" | "it" | "ru" | "uk" | "pt" => ("«", "»"),
+        "de" | "cs" | "bg" => ("„", "“"),
+        "pl" | "ro" | "hu" => ("„", "”"),
+        "en" => ("“", "”"),
+        "ja" | "zh" => ("「", "」"),
+        _ => ("\"", "\""),
+    }
+}
+
+/// Loose check for alt text that is one emoji, including ZWJ sequences,
+/// flags, keycaps and skin-tone or presentation modifiers.
+fn is_pictograph(c: char) -> bool {
+    matches!(c as u32, 0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2300..=0x23FF | 0x2B00..=0x2BFF | 0x3030 | 0x303D | 0x3297 | 0x3299)
+}
+
+/// Joiners, variation selectors, the keycap mark and tag characters
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c as u32, 0x200D | 0xFE0E | 0xFE0F | 0x20E3 | 0xE0020..=0xE007F)
+}
+
+fn is_single_emoji(text: &str) -> bool {
+    let mut pictographs = 0;
+    for c in text.chars() {
+        if is_pictograph(c) {
+            pictographs += 1;
+        } else if !(is_emoji_modifier(c) || c.is_ascii_digit() || c == '#' || c == '*') {
+            return false;
+        }
+    }
+    // ZWJ sequences (families, professions) chain several pictographs
+    let keycap = text.contains('\u{20E3}');
+    (pictographs > 0 || keycap) && text.chars().filter(|c| *c as u32 == 0x200D).count() + 2 >= pictographs
+}
+
+/// True when a list item shows nothing but emoji and icons: its text, and the alt
+/// text of any images in it, is emoji or whitespace.
+fn is_icon_item(item: &Handle) -> bool {
+    let is_decorative = |text: &str| text.chars().all(|c| c.is_whitespace() || is_pictograph(c) || is_emoji_modifier(c));
+
+    let mut images = Vec::new();
+    find_descendants(item, &["img"], &mut images);
+    let alts_decorative = images.iter().all(|image| match &image.data {
+        NodeData::Element { attrs, .. } => get_attr(attrs, "alt").is_none_or(|alt| is_decorative(&alt)),
+        _ => true,
+    });
+    alts_decorative && is_decorative(&text_content(item))
+}
+
+/// An icon-font element (FontAwesome, Glyphicons, Bootstrap and Material icons):
+/// an icon class and no text beyond whitespace or private-use glyphs.
+fn is_icon_font(attrs: &RefCell<Vec<html5ever::Attribute>>, handle: &Handle) -> bool {
+    let icon_class = get_attr(attrs, "class").is_some_and(|class| class.split_whitespace().any(|class| {
+        matches!(class, "fa" | "fas" | "far" | "fab" | "fal" | "icon" | "material-icons" | "glyphicon")
+            || ["fa-", "icon-", "glyphicon-", "bi-"].iter().any(|prefix| class.starts_with(prefix))
+    }));
+    icon_class && text_content(handle).chars().all(|c| c.is_whitespace() || ('\u{e000}'..='\u{f8ff}').contains(&c))
+}
+
+/// Backslash-escapes the characters in plain text that markdown would read as
+/// syntax. `preceding` is the last character already written, which decides
+/// whether the text starts a line and whether a leading `_` is intraword.
+fn escape_markdown(text: &str, preceding: Option<char>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut escaped = String::with_capacity(text.len());
+    // Whether the line being written began at the start of an output line,
+    // and where it begins in `escaped`
+    let mut line_begins = preceding.is_none_or(|c| c == '\n');
+    let mut line_offset = 0;
+
+    for (i, &c) in chars.iter().enumerate() {
+        let prev = if i == 0 { preceding } else { Some(chars[i - 1]) };
+        let next = chars.get(i + 1).copied();
+        let line = &escaped[line_offset..];
+        let leading = line_begins && line.trim_start_matches([' ', '\t']).is_empty();
+        let rest_of_line = || chars[i..].iter().take_while(|c| **c != '\n');
+
+        let block_marker = match c {
+            '#' | '>' => leading,
+            // List bullets, and lines of `-`/`=` that would underline the line above
+            '-' | '+' | '=' => leading && (
+                (c != '=' && next.is_none_or(char::is_whitespace))
+                    || (c != '+' && rest_of_line().all(|other| *other == c || other.is_whitespace()))
+            ),
+            // `1.` and `1)` start an ordered list
+            '.' | ')' => {
+                line_begins
+                    && !line.trim().is_empty()
+                    && line.trim_start().chars().all(|c| c.is_ascii_digit())
+                    && next.is_none_or(char::is_whitespace)
+            }
+            _ => false,
+        };
+        let inline_marker = match c {
+            '\\' | '`' | '*' | '[' | ']' => true,
+            // Intraword underscores can't open or close emphasis
+            '_' => !(prev.is_some_and(char::is_alphanumeric) && next.is_some_and(char::is_alphanumeric)),
+            '<' => next.is_some_and(|next| next.is_ascii_alphabetic() || matches!(next, '/' | '!' | '?')),
+            _ => false,
+        };
+        if block_marker || inline_marker {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+
+        if c == '\n' {
+            line_begins = true;
+            line_offset = escaped.len();
+        }
+    }
+    escaped
+}
+
+/// Backslash-escapes the characters that would end `[text](url)` early.
+fn escape_link_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '(' | ')') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn text_content(handle: &Handle) -> String {
+    let mut text = String::new();
+    collect_text(handle, &mut text);
+    text
+}
+
+fn collect_text(handle: &Handle, text: &mut String) {
+    match &handle.data {
+        NodeData::Text { contents } => text.push_str(&contents.borrow()),
+        _ => {
+            for child in handle.children.borrow().iter() {
+                collect_text(child, text);
+            }
+        }
+    }
+}
+
+fn element_name(handle: &Handle) -> Option<String> {
+    match &handle.data {
+        NodeData::Element { name, .. } => Some(name.local.to_string()),
+        _ => None,
+    }
+}
+
+/// The crumbs of a `<nav aria-label="breadcrumb">` (its list items, or links
+/// when it has no list) or of a schema.org `BreadcrumbList` (its `itemprop="name"`s).
+fn breadcrumb_trail(handle: &Handle, attrs: &RefCell<Vec<html5ever::Attribute>>) -> Option<Vec<String>> {
+    let mut items = Vec::new();
+    if get_attr(attrs, "itemtype").is_some_and(|itemtype| itemtype.ends_with("/BreadcrumbList")) {
+        find_descendants_with_attr(handle, "itemprop", "name", &mut items);
+    } else if element_name(handle).as_deref() == Some("nav")
+        && get_attr(attrs, "aria-label").is_some_and(|label| label.to_lowercase().contains("breadcrumb"))
+    {
+        find_descendants(handle, &["li"], &mut items);
+        if items.is_empty() {
+            find_descendants(handle, &["a"], &mut items);
+        }
+    } else {
+        return None;
+    }
+
+    let trail: Vec<String> = items.iter()
+        .map(|item| WHITESPACE_REGEX.replace_all(text_content(item).trim(), " ").to_string())
+        .filter(|crumb| !crumb.is_empty())
+        .collect();
+    (!trail.is_empty()).then_some(trail)
+}
+
+/// The lines of an `<address>`, split at `<br>` and block children, minus the
+/// ones holding an email or phone link.
+fn postal_lines(handle: &Handle) -> Vec<String> {
+    fn walk(handle: &Handle, lines: &mut Vec<(String, bool)>) {
+        for child in handle.children.borrow().iter() {
+            match &child.data {
+                NodeData::Text { contents } => lines.last_mut().unwrap().0.push_str(&contents.borrow()),
+                NodeData::Element { name, attrs, .. } => match name.local.as_ref() {
+                    "br" => lines.push((String::new(), false)),
+                    "a" => {
+                        let href = get_attr(attrs, "href").unwrap_or_default();
+                        if href.starts_with("mailto:") || href.starts_with("tel:") {
+                            lines.last_mut().unwrap().1 = true;
+                        }
+                        walk(child, lines);
+                    }
+                    tag if BLOCK_TAGS.contains_key(tag) => {
+                        lines.push((String::new(), false));
+                        walk(child, lines);
+                        lines.push((String::new(), false));
+                    }
+                    _ => walk(child, lines),
+                },
+                _ => {}
+            }
+        }
+    }
+
+    let mut lines = vec![(String::new(), false)];
+    walk(handle, &mut lines);
+    lines.into_iter()
+        .filter(|(_, is_contact)| !is_contact)
+        .map(|(line, _)| WHITESPACE_REGEX.replace_all(line.trim(), " ").to_string())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn find_descendants_with_attr(handle: &Handle, attr: &str, value: &str, found: &mut Vec<Handle>) {
+    for child in handle.children.borrow().iter() {
+        match &child.data {
+            NodeData::Element { attrs, .. } if get_attr(attrs, attr).as_deref() == Some(value) => {
+                found.push(child.clone());
+            }
+            _ => find_descendants_with_attr(child, attr, value, found),
+        }
+    }
+}
+
+/// Share of a block's text that sits inside links, ignoring whitespace. Blocks
+/// with fewer than two links have no density, so a lone "read more" survives.
+fn link_density(handle: &Handle) -> Option<f32> {
+    fn visible_len(text: &str) -> usize {
+        text.chars().filter(|c| !c.is_whitespace()).count()
+    }
+
+    let mut links = Vec::new();
+    find_descendants(handle, &["a"], &mut links);
+    if links.len() < 2 {
+        return None;
+    }
+
+    let total = visible_len(&text_content(handle));
+    if total == 0 {
+        return None;
+    }
+    let linked: usize = links.iter().map(|link| visible_len(&text_content(link))).sum();
+    Some(linked as f32 / total as f32)
+}
+
+fn parent_of(handle: &Handle) -> Option<Handle> {
+    let parent = handle.parent.take();
+    let upgraded = parent.as_ref().and_then(std::rc::Weak::upgrade);
+    handle.parent.set(parent);
+    upgraded
+}
+
+fn count_children(handle: &Handle, name: &str) -> usize {
+    handle.children.borrow().iter()
+        .filter(|child| element_name(child).as_deref() == Some(name))
+        .count()
+}
+
+fn find_child(handle: &Handle, names: &[&str]) -> Option<Handle> {
+    handle.children.borrow().iter()
+        .find(|child| element_name(child).is_some_and(|name| names.contains(&name.as_str())))
+        .cloned()
+}
+
+fn find_descendants(handle: &Handle, names: &[&str], found: &mut Vec<Handle>) {
+    for child in handle.children.borrow().iter() {
+        if element_name(child).is_some_and(|name| names.contains(&name.as_str())) {
+            found.push(child.clone());
+        } else {
+            find_descendants(child, names, found);
+        }
+    }
+}
+
+/// Matches the simple selectors accepted in `content_selectors` and
+/// `divider_selectors`: `tag`, `#id`, `.class`, or a tag combined with either
+/// (`div#content`, `article.post`).
+fn matches_selector(handle: &Handle, selector: &str) -> bool {
+    let NodeData::Element { name, attrs, .. } = &handle.data else {
+        return false;
+    };
+
+    let (tag, rest) = match selector.find(['#', '.']) {
+        Some(index) => selector.split_at(index),
+        None => (selector, ""),
+    };
+
+    if !tag.is_empty() && !tag.eq_ignore_ascii_case(name.local.as_ref()) {
+        return false;
+    }
+
+    let attrs = attrs.borrow();
+    let attr = |wanted: &str| attrs.iter()
+        .find(|attr| attr.name.local.as_ref() == wanted)
+        .map(|attr| attr.value.to_string())
+        .unwrap_or_default();
+
+    if let Some(id) = rest.strip_prefix('#') {
+        attr("id") == id
+    } else if let Some(class) = rest.strip_prefix('.') {
+        attr("class").split_whitespace().any(|c| c == class)
+    } else {
+        !tag.is_empty()
+    }
+}
+
+fn find_element(handle: &Handle, selector: &str) -> Option<Handle> {
+    for child in handle.children.borrow().iter() {
+        if matches_selector(child, selector) {
+            return Some(child.clone());
+        }
+        if let Some(found) = find_element(child, selector) {
+            return Some(found);
+        }
+    }
+    None
+}
+
+/// Replaces each run of `QUOTE_LEVEL` marks with that many `>`, plus a space
+/// when the line goes on. Runs can sit after an indent, for a quote inside a
+/// list item inside a quote.
+fn render_quote_levels(marked: &str) -> String {
+    let mut rendered = String::with_capacity(marked.len());
+    let mut chars = marked.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != QUOTE_LEVEL {
+            rendered.push(c);
+            continue;
+        }
+        rendered.push('>');
+        while chars.next_if_eq(&QUOTE_LEVEL).is_some() {
+            rendered.push('>');
+        }
+        if chars.peek().is_some_and(|next| *next != '\n') {
+            rendered.push(' ');
+        }
+    }
+    rendered
+}
+
+/// Indents every non-blank line of `text` by `width` spaces.
+fn indent_lines(text: &str, width: usize) -> String {
+    let indent = " ".repeat(width);
+    text.split_inclusive('\n')
+        .map(|line| if line.trim().is_empty() { line.to_string() } else { format!("{}{}", indent, line) })
+        .collect()
+}
+
+/// True when a table cell holds block content: a list, code block, quote, nested
+/// table, heading, or more than one paragraph.
+fn has_block_cells(table: &Handle) -> bool {
+    let mut cells = Vec::new();
+    find_descendants(table, &["td", "th"], &mut cells);
+    cells.iter().any(|cell| {
+        let mut blocks = Vec::new();
+        find_descendants(cell, &["ul", "ol", "dl", "pre", "blockquote", "table", "h1", "h2", "h3", "h4", "h5", "h6"], &mut blocks);
+        let mut paragraphs = Vec::new();
+        find_descendants(cell, &["p"], &mut paragraphs);
+        !blocks.is_empty() || paragraphs.len() > 1
+    })
+}
+
+/// Serializes a sanitized copy of an element back to HTML. Blank lines are
+/// dropped, since one would end the markdown HTML block early.
+fn outer_html(handle: &Handle, strip_presentational: bool) -> String {
+    use html5ever::serialize::{serialize, SerializeOpts, TraversalScope};
+    use markup5ever_rcdom::SerializableHandle;
+
+    let mut bytes = Vec::new();
+    let opts = SerializeOpts { traversal_scope: TraversalScope::IncludeNode, ..Default::default() };
+    if serialize(&mut bytes, &SerializableHandle::from(sanitized_copy(handle, strip_presentational)), opts).is_err() {
+        return String::new();
+    }
+    String::from_utf8_lossy(&bytes)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Deep-copies elements and text, leaving out `<script>`/`<style>`, comments and
+/// event handler attributes, plus `style`, `class` and `id` when
+/// `strip_presentational` is set.
+fn sanitized_copy(handle: &Handle, strip_presentational: bool) -> Handle {
+    use markup5ever_rcdom::Node;
+
+    let data = match &handle.data {
+        NodeData::Element { name, attrs, mathml_annotation_xml_integration_point, .. } => NodeData::Element {
+            name: name.clone(),
+            attrs: RefCell::new(attrs.borrow().iter()
+                .filter(|attr| {
+                    let name = attr.name.local.as_ref();
+                    let presentational = strip_presentational && matches!(name, "style" | "class" | "id");
+                    !name.starts_with("on") && !presentational
+                })
+                .cloned()
+                .collect()),
+            template_contents: RefCell::new(None),
+            mathml_annotation_xml_integration_point: *mathml_annotation_xml_integration_point,
+        },
+        NodeData::Text { contents } => NodeData::Text { contents: contents.clone() },
+        _ => NodeData::Document,
+    };
+    let copy = Node::new(data);
+    for child in handle.children.borrow().iter() {
+        let keep = match &child.data {
+            NodeData::Element { name, .. } => !matches!(name.local.as_ref(), "script" | "style"),
+            NodeData::Text { .. } => true,
+            _ => false,
+        };
+        if keep {
+            copy.children.borrow_mut().push(sanitized_copy(child, strip_presentational));
+        }
+    }
+    copy
+}
+
+/// Reads a plain-text table drawn with `|` columns and `-`/`=`/`+` rules into
+/// rows of cells. Every non-blank line has to be a rule or a `|`-bounded row
+/// with the same number of cells, otherwise the text isn't taken as a table.
+fn parse_ascii_table(text: &str) -> Option<Vec<Vec<String>>> {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let is_rule = line.contains(['-', '='])
+            && line.chars().all(|c| matches!(c, '-' | '=' | '+' | '|' | ':' | ' '));
+        if is_rule {
+            continue;
+        }
+
+        let inner = line.strip_prefix('|')?.strip_suffix('|')?;
+        let cells: Vec<String> = inner.split('|').map(|cell| cell.trim().to_string()).collect();
+        if rows.first().is_some_and(|first| first.len() != cells.len()) {
+            return None;
+        }
+        rows.push(cells);
+    }
+
+    (rows.len() >= 2 && rows[0].len() >= 2).then_some(rows)
+}
+
+/// Flattens chains like `<div><div><div>text</div></div></div>` into a single
+/// wrapper by hoisting the innermost children up to the outermost element.
+fn collapse_wrappers(handle: &Handle) {
+    if let Some(tag) = element_name(handle).filter(|tag| tag == "div" || tag == "span") {
+        loop {
+            let only_child = {
+                let children = handle.children.borrow();
+                let mut elements = children.iter().filter(|child| match &child.data {
+                    NodeData::Text { contents } => !contents.borrow().trim().is_empty(),
+                    NodeData::Comment { .. } => false,
+                    _ => true,
+                });
+                match (elements.next(), elements.next()) {
+                    (Some(child), None) if element_name(child).as_deref() == Some(tag.as_str()) => child.clone(),
+                    _ => break,
+                }
+            };
+
+            let grandchildren = only_child.children.take();
+            for grandchild in &grandchildren {
+                grandchild.parent.set(Some(std::rc::Rc::downgrade(handle)));
+            }
+            *handle.children.borrow_mut() = grandchildren;
+        }
+    }
+
+    for child in handle.children.borrow().iter() {
+        collapse_wrappers(child);
+    }
+}
+
+#[cfg(feature = "worker")]
+pub(crate) fn looks_like_feed(content_type: &str, body: &str) -> bool {
+    let content_type = content_type.to_lowercase();
+    if content_type.contains("rss") || content_type.contains("atom") {
+        return true;
+    }
+
+    let start = body.trim_start();
+    start.starts_with("<?xml") || start.starts_with("<rss") || start.starts_with("<feed")
+}
+
+/// Atom entries carry their URL in `<link href>` (preferring the `alternate`
+/// relation), RSS items in the text of `<link>`.
+#[cfg(feature = "worker")]
+fn feed_entry_link(entry: &Handle) -> Option<String> {
+    let mut links = Vec::new();
+    find_descendants(entry, &["link"], &mut links);
+
+    let mut fallback = None;
+    for link in &links {
+        if let NodeData::Element { attrs, .. } = &link.data {
+            let attrs = attrs.borrow();
+            let href = attrs.iter()
+                .find(|attr| attr.name.local.as_ref() == "href")
+                .map(|attr| attr.value.to_string());
+            let rel = attrs.iter()
+                .find(|attr| attr.name.local.as_ref() == "rel")
+                .map(|attr| attr.value.to_string());
+
+            match (href, rel.as_deref()) {
+                (Some(href), None | Some("alternate")) => return Some(href),
+                (Some(href), _) => { fallback.get_or_insert(href); }
+                (None, _) => return Some(text_content(link).trim().to_string()),
+            }
+        }
+    }
+
+    fallback
+}
+
+/// Converts an RSS or Atom document into a markdown list of entries. Returns
+/// `None` when the root element isn't a feed so the caller can fall back to HTML.
+#[cfg(feature = "worker")]
+pub(crate) fn feed_to_markdown(xml: &str, config: &ConvertConfig) -> Option<String> {
+    let dom = parse_xml_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut xml.as_bytes())
+        .ok()?;
+
+    let root = find_child(&dom.document, &["rss", "feed", "RDF"])?;
+    let channel = find_child(&root, &["channel"]).unwrap_or_else(|| root.clone());
+
+    let mut entries = Vec::new();
+    find_descendants(&root, &["item", "entry"], &mut entries);
+
+    let mut content = String::with_capacity(4096);
+
+    if let Some(title) = find_child(&channel, &["title"]) {
+        content.push_str(&format!("# {}\n\n", text_content(&title).trim()));
+    }
+
+    // Summaries are fragments of this document, so options that frame a whole
+    // document (metadata, glossaries, collected references) stay off for them
+    let summary_config = ConvertConfig {
+        include_metadata: false,
+        include_fetched_at: false,
+        max_sections: None,
+        abbreviation_glossary: false,
+        link_format: if config.link_format == LinkFormat::Reference { LinkFormat::Markdown } else { config.link_format },
+        external_link_footnotes: false,
+        final_newline: false,
+        ..config.clone()
+    };
+
+    for entry in &entries {
+        let title = find_child(entry, &["title"])
+            .map(|title| text_content(&title).trim().to_string())
+            .unwrap_or_default();
+
+        let link = feed_entry_link(entry).filter(|link| !link.is_empty());
+
+        let summary = find_child(entry, &["summary", "description", "content"])
+            .map(|summary| html_to_markdown(&text_content(&summary), summary_config.clone(), link.as_deref()))
+            .unwrap_or_default();
+
+        if !title.is_empty() {
+            content.push_str(&format!("## {}\n\n", title));
+        }
+        if let Some(link) = link {
+            content.push_str(&format!("<{}>\n\n", link));
+        }
+        if !summary.is_empty() {
+            content.push_str(&summary);
+            content.push_str("\n\n");
+        }
+    }
+
+    Some(content.trim().to_string())
+}
+
+/// The charset a page declares: the `Content-Type` header's, or else one from
+/// a `<meta charset>` or `http-equiv` tag near the top of the document.
+#[cfg(feature = "worker")]
+pub(crate) fn declared_charset(content_type: &str, html: &str) -> Option<String> {
+    let from_header = content_type.split(';').find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim().eq_ignore_ascii_case("charset").then(|| value.trim().trim_matches('"').to_ascii_lowercase())
+    });
+    from_header.or_else(|| {
+        let head = html.chars().take(1024).collect::<String>().to_ascii_lowercase();
+        META_CHARSET_REGEX.captures(&head).map(|captures| captures[1].to_string())
+    })
+}
+
+/// Finds the URL of a `<meta http-equiv="refresh" content="0;url=...">` redirect.
+#[cfg(feature = "worker")]
+pub(crate) fn meta_refresh_target(html: &str) -> Option<String> {
+    let dom = parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut html.as_bytes())
+        .ok()?;
+
+    let mut metas = Vec::new();
+    find_descendants(&dom.document, &["meta"], &mut metas);
+
+    metas.iter().find_map(|meta| {
+        let NodeData::Element { attrs, .. } = &meta.data else {
+            return None;
+        };
+        let attrs = attrs.borrow();
+        let is_refresh = attrs.iter().any(|attr| {
+            attr.name.local.as_ref() == "http-equiv" && attr.value.eq_ignore_ascii_case("refresh")
+        });
+        if !is_refresh {
+            return None;
+        }
+
+        let content = attrs.iter().find(|attr| attr.name.local.as_ref() == "content")?;
+        content.value.split(';')
+            .map(str::trim)
+            .find_map(|part| part.get(..4)
+                .filter(|prefix| prefix.eq_ignore_ascii_case("url="))
+                .map(|_| part[4..].trim().trim_matches(['\'', '"']).to_string()))
+            .filter(|target| !target.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str, config: ConvertConfig) -> String {
+        html_to_markdown(html, config, None)
+    }
+
+    #[test]
+    fn preserved_html_drops_presentational_attributes() {
+        let html = r#"<table><tr><td style="color:red" class="cell" id="c"><p>One</p><p>Two</p></td></tr></table>
+            <details class="box" style="margin:0"><summary>More</summary><p>Body</p></details>"#;
+        let markdown = convert(html, ConvertConfig::default());
+        assert!(markdown.contains("<td><p>One</p><p>Two</p></td>"), "{}", markdown);
+        assert!(markdown.contains("<details>\n"), "{}", markdown);
+        assert!(!markdown.contains("style="), "{}", markdown);
+
+        let config = ConvertConfig { strip_html_attributes: false, ..Default::default() };
+        let markdown = convert(html, config);
+        assert!(markdown.contains(r#"<td style="color:red" class="cell" id="c">"#), "{}", markdown);
+    }
+
+    #[test]
+    fn cite_renders_as_italics() {
+        let markdown = convert("<p>As argued in <cite>The Mythical Man-Month</cite>, adding people slows things down.</p>", ConvertConfig::default());
+        assert_eq!(markdown.trim(), "As argued in *The Mythical Man-Month*, adding people slows things down.");
+    }
+
+    #[test]
+    fn clean_whitespace_leaves_code_blocks_untouched() {
+        let code = "fn main() {\n    let  x = 1;\n\n\n        nested();\n\t\ttabbed();\n    trailing   \n}";
+        let html = format!("<p>Intro</p><pre>{}</pre>", code);
+        let config = ConvertConfig { clean_whitespace: true, ..Default::default() };
+        let markdown = convert(&html, config);
+        assert!(markdown.contains(&format!("```\n{}\n```", code)), "{}", markdown);
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn atom_feed_becomes_entry_list() {
+        let feed = r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+              <title>Example Blog</title>
+              <entry>
+                <title>First post</title>
+                <link href="https://example.com/first"/>
+                <summary type="html">&lt;p&gt;Hello &lt;b&gt;world&lt;/b&gt;&lt;/p&gt;</summary>
+              </entry>
+              <entry>
+                <title>Second post</title>
+                <link rel="alternate" href="https://example.com/second"/>
+                <summary>Plain summary</summary>
+              </entry>
+            </feed>"#;
+        assert!(looks_like_feed("application/atom+xml", feed));
+
+        // Document-level options don't leak into each entry's summary
+        let config = ConvertConfig { include_metadata: true, ..Default::default() };
+        let markdown = feed_to_markdown(feed, &config).unwrap();
+        assert!(markdown.starts_with("# Example Blog\n\n## First post\n\n<https://example.com/first>\n\nHello **world**"), "{}", markdown);
+        assert!(markdown.contains("## Second post\n\n<https://example.com/second>\n\nPlain summary"), "{}", markdown);
+        assert!(!markdown.contains("---"), "{}", markdown);
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn outline_nests_headings() {
+        let html = "<h1>Guide</h1><h2>Install</h2><h3>Linux</h3><h3>macOS</h3><h2>Usage</h2><h1>Appendix</h1>";
+        let config = ConvertConfig { preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        let outline = convert_document(html, config, None).outline();
+        let expected = serde_json::json!([
+            {"level": 1, "text": "Guide", "children": [
+                {"level": 2, "text": "Install", "children": [
+                    {"level": 3, "text": "Linux", "children": []},
+                    {"level": 3, "text": "macOS", "children": []},
+                ]},
+                {"level": 2, "text": "Usage", "children": []},
+            ]},
+            {"level": 1, "text": "Appendix", "children": []},
+        ]);
+        assert_eq!(serde_json::to_value(&outline).unwrap(), expected);
+    }
+
+    #[test]
+    fn pre_code_gives_a_single_fence() {
+        let html = r#"<pre><code class="language-rust">fn main() {
+    println!("hi");
+}</code></pre>"#;
+        let markdown = convert(html, ConvertConfig::default());
+        assert_eq!(markdown.trim(), "```rust\nfn main() {\n    println!(\"hi\");\n}\n```");
+    }
+
+
+    #[test]
+    fn wide_table_is_capped_with_an_ellipsis_column() {
+        let header: String = (1..=6).map(|i| format!("<th>H{}</th>", i)).collect();
+        let row: String = (1..=6).map(|i| format!("<td>{}</td>", i)).collect();
+        let html = format!("<table><tr>{}</tr><tr>{}</tr></table>", header, row);
+        let config = ConvertConfig { max_table_columns: Some(3), ..Default::default() };
+        let markdown = convert(&html, config);
+        assert_eq!(markdown.trim(), "| H1 | H2 | H3 | … |\n| -- | -- | -- | - |\n| 1  | 2  | 3  | … |");
+    }
+
+    #[test]
+    fn content_selectors_fall_through_to_a_later_match() {
+        let html = r#"<nav><p>Menu</p></nav><div id="content"><p>Body text</p></div><footer><p>Footer</p></footer>"#;
+        let config = ConvertConfig {
+            content_selectors: vec!["article".into(), "#content".into(), "main".into()],
+            ..Default::default()
+        };
+        assert_eq!(convert(html, config).trim(), "Body text");
+    }
+
+    #[test]
+    fn metadata_is_kept_for_an_empty_body() {
+        let html = r#"<html><head><meta property="og:title" content="Moved page">
+            <meta property="og:description" content="This page has moved."></head><body></body></html>"#;
+        let config = ConvertConfig { include_metadata: true, metadata_format: MetadataFormat::Yaml, ..Default::default() };
+        let formatter = convert_document(html, config, None);
+        assert!(formatter.body_is_empty());
+        assert_eq!(formatter.result(), "---\ntitle: \"Moved page\"\ndescription: \"This page has moved.\"\n---\n");
+    }
+
+    #[test]
+    fn glossary_lists_unique_abbreviations() {
+        let html = r#"<p>The <abbr title="World Health Organization">WHO</abbr> and <abbr title="HyperText Markup Language">HTML</abbr>.</p>
+            <p>Again the <abbr title="World Health Organization">WHO</abbr>.</p>"#;
+        let config = ConvertConfig { abbreviation_glossary: true, ..Default::default() };
+        let markdown = convert(html, config);
+        assert!(markdown.ends_with("## Glossary\n\n* **WHO**: World Health Organization\n* **HTML**: HyperText Markup Language\n"), "{}", markdown);
+    }
+
+    #[test]
+    fn custom_lazy_attribute_wins_over_src() {
+        let html = r#"<img src="/placeholder.gif" data-hi-res-src="/photo.jpg" data-src="/small.jpg" alt="Photo">"#;
+        let config = ConvertConfig { image_src_attributes: vec!["data-hi-res-src".into(), "data-src".into()], ..Default::default() };
+        assert_eq!(convert(html, config).trim(), "![Photo](/photo.jpg)");
+    }
+
+    #[test]
+    fn details_without_summary_gets_the_default() {
+        let config = ConvertConfig { default_summary: Some("Show more".into()), ..Default::default() };
+        let markdown = convert("<details><p>Hidden text</p></details>", config);
+        assert_eq!(markdown.trim(), "<details>\n<summary>Show more</summary>\n\nHidden text\n\n</details>");
+    }
+
+    #[test]
+    fn frontmatter_uses_the_configured_delimiter() {
+        let html = r#"<html><head><meta property="og:title" content="Post"><meta property="article:author" content="Ann"></head>
+            <body><p>Body</p></body></html>"#;
+        let config = ConvertConfig { include_metadata: true, frontmatter_delimiter: "+++".into(), ..Default::default() };
+        assert_eq!(convert(html, config), "# Post\n\n+++\nAuthor: Ann\n+++\n\nBody\n");
+    }
+
+    fn preserving_breaks() -> ConvertConfig {
+        ConvertConfig {
+            cleaning_rules: CleaningRules { preserve_line_breaks: true, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    fn hard_break(style: HardBreakStyle) -> String {
+        convert("<p>first<br>second</p>", ConvertConfig { hard_break_style: style, ..preserving_breaks() })
+    }
+
+    #[test]
+    fn hard_break_as_trailing_spaces() {
+        assert_eq!(hard_break(HardBreakStyle::Spaces).trim(), "first  \nsecond");
+    }
+
+    #[test]
+    fn hard_break_as_backslash() {
+        assert_eq!(hard_break(HardBreakStyle::Backslash).trim(), "first\\\nsecond");
+    }
+
+    #[test]
+    fn hard_break_as_html() {
+        assert_eq!(hard_break(HardBreakStyle::Html).trim(), "first<br>\nsecond");
+    }
+
+    #[test]
+    fn hard_break_inside_pre_is_a_newline() {
+        for style in [HardBreakStyle::Spaces, HardBreakStyle::Backslash, HardBreakStyle::Html] {
+            let markdown = convert("<pre>first<br>second</pre>", ConvertConfig { hard_break_style: style, ..Default::default() });
+            assert_eq!(markdown.trim(), "```\nfirst\nsecond\n```");
+        }
+    }
+
+    #[test]
+    fn multi_line_classed_code_becomes_a_fence() {
+        let html = "<p>Run:</p><code class=\"language-sh\">cargo build\ncargo test</code><p>Or <code class=\"language-sh\">make</code>.</p>";
+        let markdown = convert(html, ConvertConfig::default());
+        assert!(markdown.contains("```sh\ncargo build\ncargo test\n```"), "{}", markdown);
+        assert!(markdown.contains("Or `make`."), "{}", markdown);
+    }
+
+    #[test]
+    fn whitespace_modes_differ() {
+        let html = "<div>\n  <p>one   two\n   three</p>\n\n\n  <p><b>bold</b> <i>it</i></p>\n</div>";
+        let mode = |mode| convert(html, ConvertConfig { whitespace_mode: Some(mode), ..Default::default() });
+
+        let off = mode(WhitespaceMode::Off);
+        assert!(off.starts_with("one   two\n   three\n\n\n"), "{:?}", off);
+
+        // Runs collapse within text, inline spacing survives and blocks stay apart
+        assert_eq!(mode(WhitespaceMode::Inline), "one two three\n\n**bold** *it*\n");
+
+        // Aggressive turns every whitespace run into a break
+        let aggressive = mode(WhitespaceMode::Aggressive);
+        assert!(aggressive.starts_with("one\n\ntwo\n\nthree\n\n"), "{:?}", aggressive);
+    }
+
+    #[test]
+    fn strikethrough_cell_width_counts_the_markers() {
+        let html = "<table><tr><th>Item</th><th>Price</th></tr><tr><td><s>Old</s> New</td><td>5</td></tr></table>";
+        let markdown = convert(html, ConvertConfig::default());
+        assert_eq!(markdown, "| Item        | Price |\n| ----------- | ----- |\n| ~~Old~~ New | 5     |\n");
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn split_sections_at_h2() {
+        let markdown = "Intro line\n\n## Install\n\nRun it.\n\n### Details\n\nMore.\n\n## Usage\n\nUse it.\n";
+        let sections = serde_json::to_value(split_sections(markdown, 2)).unwrap();
+        assert_eq!(sections, serde_json::json!([
+            {"heading": "", "level": 0, "content": "Intro line"},
+            {"heading": "Install", "level": 2, "content": "Run it.\n\n### Details\n\nMore."},
+            {"heading": "Usage", "level": 2, "content": "Use it."},
+        ]));
+    }
+
+    #[test]
+    fn nbsp_becomes_a_plain_space() {
+        let html = "<p>Price:&nbsp;10&nbsp;EUR</p>";
+        assert_eq!(convert(html, ConvertConfig::default()), "Price: 10 EUR\n");
+        let config = ConvertConfig { whitespace_mode: Some(WhitespaceMode::Inline), ..Default::default() };
+        assert_eq!(convert(html, config), "Price: 10 EUR\n");
+    }
+
+    #[test]
+    fn ordered_list_with_paren_delimiter() {
+        let config = ConvertConfig { ordered_delimiter: OrderedDelimiter::Paren, ..Default::default() };
+        assert_eq!(convert("<ol><li>One</li><li>Two</li></ol>", config), "1) One\n2) Two\n");
+    }
+
+    #[test]
+    fn summary_comes_from_the_first_substantial_paragraph() {
+        let html = "<p>By Ann</p><p>The quick brown fox jumps over the lazy dog near the river bank today.</p>";
+        let config = ConvertConfig {
+            include_metadata: true,
+            summary_words: Some(5),
+            metadata_format: MetadataFormat::Yaml,
+            ..Default::default()
+        };
+        assert!(convert(html, config).starts_with("---\nsummary: \"The quick brown fox jumps…\"\n---\n"));
+    }
+
+    #[test]
+    fn ordered_list_with_uppercase_alpha_type() {
+        let config = ConvertConfig { list_type_markers: true, ..Default::default() };
+        assert_eq!(convert(r#"<ol type="A"><li>One</li><li>Two</li></ol>"#, config), "A. One\n\nB. Two\n");
+    }
+
+    #[test]
+    fn ordered_list_with_lowercase_roman_type() {
+        let config = ConvertConfig { list_type_markers: true, ..Default::default() };
+        assert_eq!(convert(r#"<ol type="i" start="3"><li>x</li><li>y</li></ol>"#, config), "iii. x\n\niv. y\n");
+    }
+
+    #[test]
+    fn truncated_url_text_becomes_an_autolink() {
+        let html = r#"<p><a href="https://www.example.com/very-long-path/to/page">example.com/very-long…</a></p>"#;
+        let config = ConvertConfig { resolve_truncated_links: true, include_links: true, ..Default::default() };
+        assert_eq!(convert(html, config), "<https://www.example.com/very-long-path/to/page>\n");
+    }
+
+    #[test]
+    fn image_query_and_fragment_are_stripped() {
+        let html = r#"<img src="https://cdn.example.com/a.png?v=123#x" alt="A">"#;
+        let config = ConvertConfig { strip_image_query: true, ..Default::default() };
+        assert_eq!(convert(html, config), "![A](https://cdn.example.com/a.png)\n");
+    }
+
+    #[test]
+    fn language_less_pre_fenced_or_indented() {
+        let html = "<p>Example:</p><pre>plain\n  code</pre>";
+        assert_eq!(convert(html, ConvertConfig::default()), "Example:\n\n```\nplain\n  code\n```\n");
+        let config = ConvertConfig { indent_plain_code: true, ..Default::default() };
+        assert_eq!(convert(html, config), "Example:\n\n    plain\n      code\n");
+    }
+
+    #[test]
+    fn double_br_becomes_a_paragraph_break() {
+        let config = ConvertConfig { br_paragraphs: true, ..preserving_breaks() };
+        assert_eq!(convert("<p>text<br><br>more</p>", config), "text\n\nmore\n");
+        assert_eq!(convert("<p>text<br>more</p>", ConvertConfig { br_paragraphs: true, ..preserving_breaks() }), "text  \nmore\n");
+        assert_eq!(convert("<p>text<br><br>more</p>", ConvertConfig { br_paragraphs: true, ..Default::default() }), "text\n\nmore\n");
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn meta_refresh_target_is_found() {
+        let html = r#"<html><head><meta http-equiv="Refresh" content="0; URL='/moved/here'"></head><body></body></html>"#;
+        assert_eq!(meta_refresh_target(html).as_deref(), Some("/moved/here"));
+        let html = r#"<html><head><meta http-equiv="refresh" content="30"></head></html>"#;
+        assert_eq!(meta_refresh_target(html), None);
+        assert_eq!(meta_refresh_target("<p>No redirect</p>"), None);
+    }
+
+    #[test]
+    fn link_domain_badge_is_appended() {
+        let html = r#"<p><a href="https://www.example.com/a">Story</a></p>"#;
+        let config = ConvertConfig { link_domain_badge: true, include_links: true, ..Default::default() };
+        assert_eq!(convert(html, config), "[Story](https://www.example.com/a) (example.com)\n");
+    }
+
+    #[test]
+    fn row_header_cells_are_bolded() {
+        let html = r#"<table><tr><th>Name</th><th>Q1</th></tr><tr><th scope="row">North</th><td>5</td></tr></table>"#;
+        let config = ConvertConfig { bold_row_headers: true, ..Default::default() };
+        assert_eq!(convert(html, config), "| Name      | Q1 |\n| --------- | -- |\n| **North** | 5  |\n");
+    }
+
+    #[test]
+    fn final_newline_and_no_bom() {
+        let html = "\u{feff}<p>Hi</p>\n\n";
+        let with_newline = convert(html, ConvertConfig::default());
+        assert_eq!(with_newline, "Hi\n");
+        let without = convert(html, ConvertConfig { final_newline: false, ..Default::default() });
+        assert_eq!(without, "Hi");
+        assert!(!with_newline.contains('\u{feff}') && !without.contains('\u{feff}'));
+    }
+
+    #[test]
+    fn video_poster_and_first_source() {
+        let html = r#"<video poster="/poster.jpg"><source src="/clip.webm" type="video/webm"><source src="/clip.mp4"></video>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "![Video](/poster.jpg)\n[Video](/clip.webm)\n");
+    }
+
+    #[test]
+    fn tt_renders_as_backticks_via_the_map() {
+        let html = "<p>Type <tt>ls -la</tt> or <kbd>Ctrl</kbd> and <var>x</var></p>";
+        assert_eq!(convert(html, ConvertConfig::default()), "Type `ls -la` or `Ctrl` and *x*\n");
+        let config = ConvertConfig {
+            code_tag_styles: BTreeMap::from([("tt".to_string(), CodeStyle::Html)]),
+            ..Default::default()
+        };
+        assert_eq!(convert(html, config), "Type <tt>ls -la</tt> or `Ctrl` and *x*\n");
+    }
+
+    #[test]
+    fn collapsed_wrappers_convert_the_same() {
+        let html = "<div><div><div><span><p>Hello <b>world</b></p></span></div></div></div><div><div><ul><li>a</li></ul></div></div>";
+        let plain = convert(html, ConvertConfig::default());
+        let collapsed = convert(html, ConvertConfig { collapse_wrappers: true, ..Default::default() });
+        assert_eq!(plain, "Hello **world**\n\n* a\n");
+        assert_eq!(collapsed, plain);
+
+        let config = ConvertConfig { collapse_wrappers: true, ..Default::default() };
+        let (_dom, root) = parse_root("<div id=\"outer\"><div><div><p>x</p></div></div></div>", &config);
+        let outer = find_element(&root, "#outer").unwrap();
+        let children = outer.children.borrow();
+        assert_eq!(children.len(), 1);
+        assert_eq!(element_name(&children[0]).as_deref(), Some("p"));
+    }
+
+    #[test]
+    fn cdn_resize_urls_are_canonicalized() {
+        let html = concat!(
+            r#"<img src="https://res.cloudinary.com/demo/image/upload/w_300,h_200,c_fill/v123/pic.jpg" alt="A">"#,
+            r#"<img src="https://example.com/wp-content/uploads/2024/01/pic-300x200.jpg" alt="B">"#,
+        );
+        let config = ConvertConfig { canonical_image_urls: true, ..Default::default() };
+        assert_eq!(
+            convert(html, config),
+            "![A](https://res.cloudinary.com/demo/image/upload/v123/pic.jpg)\n![B](https://example.com/wp-content/uploads/2024/01/pic.jpg)\n"
+        );
+
+        let html = r#"<img src="https://i0.wp.com/example.com/pic.jpg?resize=300%2C200" alt="C">"#;
+        let config = ConvertConfig {
+            image_url_rewrites: vec![UrlRewrite { pattern: r"\?resize=.*$".to_string(), replacement: String::new() }],
+            ..Default::default()
+        };
+        assert_eq!(convert(html, config), "![C](https://i0.wp.com/example.com/pic.jpg)\n");
+    }
+
+    #[test]
+    fn internal_link_becomes_a_wikilink() {
+        let html = r#"<p><a href="/notes/rust-traits">Rust traits</a> and <a href="https://other.org/x">Other</a> <img src="/img/diagram.png" alt="Diagram"></p>"#;
+        let config = ConvertConfig { link_format: LinkFormat::Wikilink, include_links: true, ..Default::default() };
+        assert_eq!(
+            html_to_markdown(html, config, Some("https://example.com/notes/index")),
+            "[[rust-traits|Rust traits]] and [Other](https://other.org/x) \n![[diagram.png|Diagram]]\n"
+        );
+    }
+
+    #[test]
+    fn alt_text_entities_are_decoded() {
+        let html = r#"<img src="/a.png" alt="Rock &amp; Roll">"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "![Rock & Roll](/a.png)\n");
+    }
+
+    #[test]
+    fn empty_paragraphs_are_skipped() {
+        let html = "<p>One</p><p></p><p> </p><p>&nbsp;</p><p>Two</p>";
+        let config = ConvertConfig { skip_empty_blocks: true, ..Default::default() };
+        assert_eq!(convert(html, config), "One\n\nTwo\n");
+    }
+
+    #[test]
+    fn inline_styles_become_emphasis() {
+        let html = concat!(
+            r#"<p><span style="font-weight: bold">Bold</span> and <span style="font-style:italic">it</span>"#,
+            r#" and <span style="font-weight:700;font-style:italic">both</span></p>"#,
+        );
+        let config = ConvertConfig { style_emphasis: true, ..Default::default() };
+        assert_eq!(convert(html, config), "**Bold** and *it* and ***both***\n");
+    }
+
+    #[test]
+    fn inventory_lists_links_and_images() {
+        let html = r#"<p><a href="/docs">Docs</a> and <a href="https://other.org/">Other</a></p><img src="img/a.png" alt="A">"#;
+        let config = ConvertConfig { inventory: true, include_links: true, ..Default::default() };
+        let formatter = convert_document(html, config, Some("https://example.com/guide/"));
+        let expected = serde_json::json!({
+            "links": [
+                { "text": "Docs", "href": "https://example.com/docs" },
+                { "text": "Other", "href": "https://other.org/" },
+            ],
+            "images": [{ "alt": "A", "src": "https://example.com/guide/img/a.png" }],
+        });
+        let actual = serde_json::json!({ "links": formatter.links, "images": formatter.images });
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn list_item_value_jumps_the_numbering() {
+        let html = r#"<ol><li>a</li><li value="10">b</li><li>c</li></ol>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "1. a\n10. b\n11. c\n");
+    }
+
+    #[test]
+    fn ascii_table_becomes_a_markdown_table() {
+        let html = "<pre>+-----+-----+\n| a   | b   |\n+-----+-----+\n| 1   | 2   |\n+-----+-----+</pre>";
+        let config = ConvertConfig { ascii_tables: true, ..Default::default() };
+        assert_eq!(convert(html, config), "| a | b |\n| - | - |\n| 1 | 2 |\n");
+    }
+
+    #[test]
+    fn labeled_landmark_gets_a_heading() {
+        let html = r#"<section aria-label="Related posts"><p>Stuff</p></section><section aria-label="Has one"><h3>Own</h3></section>"#;
+        let config = ConvertConfig { landmark_headings: true, preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        assert_eq!(convert(html, config), "## Related posts\n\nStuff\n\n### Own\n");
+    }
+
+    #[test]
+    fn max_sections_truncates_with_a_note() {
+        let html = "<h2>A</h2><p>a</p><h2>B</h2><p>b</p><h2>C</h2><p>c</p>";
+        let config = ConvertConfig { max_sections: Some(2), preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        assert_eq!(convert(html, config), "## A\n\na\n\n## B\n\nb\n\n*Truncated: showing the first 2 of 3 sections.*\n");
+    }
+
+    #[test]
+    fn details_in_a_list_item_keep_the_indent() {
+        let html = "<ul><li>Item<details><summary>More</summary><p>Hidden text</p></details></li><li>Next</li></ul>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "* Item\n\n  <details>\n  <summary>More</summary>\n\n  Hidden text\n\n  </details>\n* Next\n"
+        );
+    }
+
+    #[test]
+    fn language_classes_are_canonicalized() {
+        let html = r#"<pre><code class="lang-js">let a = 1;</code></pre><pre><code class="language-py">x = 1</code></pre>"#;
+        let config = ConvertConfig { canonical_languages: true, ..Default::default() };
+        assert_eq!(convert(html, config), "```javascript\nlet a = 1;\n```\n\n```python\nx = 1\n```\n");
+    }
+
+    #[test]
+    fn inline_metadata_is_a_single_line() {
+        let html = concat!(
+            r#"<html><head><meta property="og:title" content="T"><meta property="article:author" content="Ann">"#,
+            r#"<meta property="article:published_time" content="2024-01-02"></head><body><p>Body</p></body></html>"#,
+        );
+        let config = ConvertConfig { include_metadata: true, metadata_format: MetadataFormat::Inline, ..Default::default() };
+        assert_eq!(convert(html, config), "title: T | author: Ann | date: 2024-01-02\n\nBody\n");
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn articles_are_separated() {
+        let html = "<article><p>One</p></article><article><p>Two</p></article><article><p>Three</p></article>";
+        let config = ConvertConfig { article_separation: Some(ArticleSeparation::Rule), ..Default::default() };
+        assert_eq!(convert(html, config), "One\n\n---\n\nTwo\n\n---\n\nThree\n");
+        assert_eq!(convert_articles(html, ConvertConfig::default(), None), ["One\n", "Two\n", "Three\n"]);
+    }
+
+    #[test]
+    fn breadcrumb_trail_goes_into_metadata() {
+        let html = r#"<nav aria-label="Breadcrumb"><ol><li><a href="/">Home</a></li><li><a href="/docs">Docs</a></li><li>Page</li></ol></nav><p>Body</p>"#;
+        let config = ConvertConfig { include_breadcrumbs: true, include_metadata: true, ..Default::default() };
+        assert!(convert(html, config).starts_with("---\nBreadcrumbs: Home > Docs > Page\n---\n"));
+    }
+
+    #[test]
+    fn cell_whitespace_is_collapsed() {
+        let html = "<table><tr><th>A</th><th>B</th></tr><tr><td>x  \n  y</td><td>z</td></tr></table>";
+        assert_eq!(convert(html, ConvertConfig::default()), "| A   | B |\n| --- | - |\n| x y | z |\n");
+    }
+
+    #[test]
+    fn french_quotes_for_q() {
+        let html = "<p><q>Bonjour</q> et <q>salut</q></p>";
+        let config = ConvertConfig { quote_locale: Some("fr".to_string()), ..Default::default() };
+        assert_eq!(convert(html, config), "« Bonjour » et « salut »\n");
+    }
+
+    #[test]
+    fn emoji_images_become_characters() {
+        let html = r#"<p>Hi <img class="emoji" alt="😀" src="/e.png"> <img class="wp-smiley" alt=":)" src="/s.png"> <img alt="Pic" src="/p.png"></p>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "Hi 😀 :) \n![Pic](/p.png)\n");
+        let html = r#"<p>Hi <img class="emoji" alt="😀" src="/e.png"></p>"#;
+        let config = ConvertConfig { emoji_as_text: false, ..Default::default() };
+        assert_eq!(convert(html, config), "Hi \n![😀](/e.png)\n");
+    }
+
+    #[test]
+    fn link_heavy_blocks_are_dropped() {
+        let html = concat!(
+            r#"<div><a href="/a">A</a> <a href="/b">B</a> <a href="/c">C</a></div>"#,
+            r#"<p>Plain prose with <a href="/x">one link</a> in a long sentence that is mostly text.</p>"#,
+        );
+        let config = ConvertConfig { max_link_density: Some(0.5), include_links: true, ..Default::default() };
+        assert_eq!(convert(html, config), "Plain prose with [one link](/x) in a long sentence that is mostly text.\n");
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn source_map_points_lines_at_elements() {
+        let html = "<h1>Top</h1><p>Intro</p><h2>Sub</h2><p>x</p>";
+        let config = ConvertConfig { source_map: true, preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        let formatter = convert_document(html, config, None);
+        let markdown = formatter.result();
+        assert_eq!(markdown.lines().nth(4), Some("## Sub"));
+        let entry = formatter.source_map(&markdown).into_iter().find(|entry| entry.start_line == 5).unwrap();
+        assert_eq!((entry.tag.as_str(), entry.end_line, entry.path.as_str()), ("h2", 5, "html > body > h2"));
+    }
+
+    #[test]
+    fn mixed_lists_nest_three_levels() {
+        let html = "<ol><li>one<ul><li>a<ol><li>deep</li></ol></li></ul></li><li>two</li></ol>";
+        assert_eq!(convert(html, ConvertConfig::default()), "1. one\n   * a\n     1. deep\n2. two\n");
+    }
+
+    #[test]
+    fn non_ascii_cells_pad_by_characters() {
+        let html = "<table><tr><th>Name</th><th>Val</th></tr><tr><td>café</td><td>日本</td></tr></table>";
+        assert_eq!(convert(html, ConvertConfig::default()), "| Name | Val |\n| ---- | --- |\n| café | 日本  |\n");
+    }
+
+    #[test]
+    fn address_email_becomes_contact() {
+        let html = r#"<address>Contact <a href="mailto:me@example.com">me</a></address><p>Body</p>"#;
+        let config = ConvertConfig { include_contact: true, include_metadata: true, ..Default::default() };
+        assert!(convert(html, config).starts_with("---\nEmail: me@example.com\n---\n"));
+    }
+
+    #[test]
+    fn heading_numbers_are_trimmed() {
+        let html = "<h2>1. Introduction:</h2><h3>2.1 Setup</h3><h3>Step 3</h3>";
+        let config = ConvertConfig { trim_heading_numbers: true, preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        assert_eq!(convert(html, config), "## Introduction\n\n### Setup\n\n### Step 3\n");
+    }
+
+    #[test]
+    fn highlight_lines_go_into_the_fence_info() {
+        let config = || ConvertConfig { code_highlight_lines: true, ..Default::default() };
+        let html = r#"<pre><code class="language-rust" data-line="2-3">fn a() {}</code></pre>"#;
+        assert_eq!(convert(html, config()), "```rust {2-3}\nfn a() {}\n```\n");
+        let html = r#"<pre data-line="1,4"><code class="language-rust">fn a() {}</code></pre>"#;
+        assert_eq!(convert(html, config()), "```rust {1,4}\nfn a() {}\n```\n");
+    }
+
+    #[test]
+    fn heading_marks_are_stripped() {
+        let html = "<h2>Intro <mark>new</mark></h2><p>x <mark>hi</mark></p>";
+        let headings = || ConvertConfig { preserve_headings: true, max_heading_level: 6, ..Default::default() };
+        assert_eq!(convert(html, ConvertConfig { strip_heading_markers: true, ..headings() }), "## Intro new\n\nx ==hi==\n");
+        assert_eq!(convert(html, headings()), "## Intro ==new==\n\nx ==hi==\n");
+    }
+
+    #[test]
+    fn references_are_grouped_by_domain() {
+        let html = r#"<p><a href="https://a.com/1">A1</a> <a href="https://b.org/x">B</a> <a href="https://a.com/2">A2</a></p>"#;
+        let config = ConvertConfig {
+            link_format: LinkFormat::Reference,
+            group_references_by_domain: true,
+            include_links: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert(html, config),
+            "[A1][1] [B][2] [A2][3]\n\n<!-- a.com -->\n[1]: https://a.com/1\n[3]: https://a.com/2\n\n<!-- b.org -->\n[2]: https://b.org/x\n"
+        );
+    }
+
+    #[test]
+    fn list_in_a_cell_keeps_the_table_as_html() {
+        let html = "<table><tr><th>A</th></tr><tr><td><ul><li>x</li><li>y</li></ul></td></tr></table>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "<table><tbody><tr><th>A</th></tr><tr><td><ul><li>x</li><li>y</li></ul></td></tr></tbody></table>\n"
+        );
+    }
+
+    #[test]
+    fn emoji_only_items_are_skipped() {
+        let html = "<ul><li>😀</li><li>🎉 ✨</li><li>Real item 🎉</li></ul>";
+        let config = ConvertConfig { skip_emoji_items: true, ..Default::default() };
+        assert_eq!(convert(html, config), "* Real item 🎉\n");
+    }
+
+    #[test]
+    fn code_block_in_a_list_item_is_indented() {
+        let html = "<ul><li>Step<pre><code>cargo build\ncargo test</code></pre></li><li>Next</li></ul>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "* Step\n\n  ```\n  cargo build\n  cargo test\n  ```\n* Next\n"
+        );
+    }
+
+    #[test]
+    fn br_in_each_context() {
+        let html = "<p>a<br>b</p>";
+        assert_eq!(convert(html, preserving_breaks()), "a  \nb\n");
+        assert_eq!(convert(html, ConvertConfig::default()), "a b\n");
+
+        let html = "<table><tr><th>H</th></tr><tr><td>x<br>y</td></tr></table>";
+        for config in [preserving_breaks(), ConvertConfig::default()] {
+            assert_eq!(convert(html, config), "| H      |\n| ------ |\n| x<br>y |\n");
+        }
+    }
+
+    #[test]
+    fn rel_author_link_fills_the_author() {
+        let html = r#"<html><body><a rel="author" href="/u/ann">Ann Lee</a><p>Body</p></body></html>"#;
+        let config = ConvertConfig { include_metadata: true, ..Default::default() };
+        assert!(convert(html, config).starts_with("---\nAuthor: Ann Lee\n---\n"));
+    }
+
+    #[test]
+    fn replacements_apply_within_limits() {
+        let replacement = |pattern: &str, replacement: &str, regex: bool| Replacement {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            regex,
+        };
+        let html = "<p>Mail ann@example.com, cost $5</p>";
+        let config = ConvertConfig {
+            replacements: vec![replacement(r"(\w+)@example\.com", "$1 at example", true), replacement("$5", "$6", false)],
+            ..Default::default()
+        };
+        assert_eq!(convert(html, config), "Mail ann at example, cost $6\n");
+
+        let mut replacements: Vec<_> = (0..MAX_REPLACEMENTS).map(|_| replacement("x", "x", false)).collect();
+        replacements.push(replacement("a", "b", false));
+        let config = ConvertConfig { replacements, ..Default::default() };
+        assert_eq!(convert("<p>a</p>", config), "a\n");
+
+        let config = ConvertConfig {
+            replacements: vec![
+                replacement(&"a".repeat(MAX_REPLACEMENT_PATTERN_LEN + 1), "b", false),
+                replacement("a", &"b".repeat(MAX_REPLACEMENT_LEN + 1), false),
+            ],
+            ..Default::default()
+        };
+        assert_eq!(convert("<p>a</p>", config), "a\n");
+    }
+
+    #[test]
+    fn ordered_list_start_and_reversed() {
+        let html = r#"<ol start="3"><li>a</li><li>b</li><li>c</li></ol><ol reversed><li>x</li><li>y</li><li>z</li></ol>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "3. a\n4. b\n5. c\n3. x\n2. y\n1. z\n");
+    }
+
+    #[cfg(feature = "worker")]
+    #[test]
+    fn declared_charset_from_header_or_meta() {
+        assert_eq!(declared_charset("text/html; charset=ISO-8859-1", "<p>x</p>").as_deref(), Some("iso-8859-1"));
+        assert_eq!(declared_charset("text/html", r#"<html><head><meta charset="windows-1252"></head>"#).as_deref(), Some("windows-1252"));
+        let html = r#"<meta http-equiv="Content-Type" content="text/html; charset=Shift_JIS">"#;
+        assert_eq!(declared_charset("text/html", html).as_deref(), Some("shift_jis"));
+        assert_eq!(declared_charset("text/html", "<p>x</p>"), None);
+    }
+
+    #[test]
+    fn divider_selector_becomes_a_rule() {
+        let html = r#"<p>A</p><div class="divider"></div><p>B</p><div class="other"></div>"#;
+        let config = ConvertConfig { divider_selectors: vec!["div.divider".to_string()], ..Default::default() };
+        assert_eq!(convert(html, config), "A\n\n---\n\nB\n");
+    }
+
+    #[test]
+    fn only_new_tab_links_become_footnotes() {
+        let html = r#"<p><a href="https://a.com/" target="_blank">Ext</a> and <a href="/local">Local</a></p>"#;
+        let config = ConvertConfig { external_link_footnotes: true, include_links: true, ..Default::default() };
+        assert_eq!(convert(html, config), "Ext[^1] and [Local](/local)\n\n[^1]: https://a.com/\n");
+    }
+
+    #[test]
+    fn pagination_links_are_recorded() {
+        let html = r#"<html><head><link rel="next" href="/page/3"><link rel="prev" href="/page/1"></head><body><p>Body</p></body></html>"#;
+        let config = ConvertConfig { include_pagination: true, include_metadata: true, ..Default::default() };
+        assert_eq!(convert(html, config), "---\nNext page: /page/3\nPrevious page: /page/1\n---\n\nBody\n");
+    }
+
+    #[test]
+    fn empty_icon_elements_are_dropped() {
+        let html = r#"<p><i class="fa fa-star"></i> Starred <i>really</i> <span class="icon icon-x"></span></p>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "Starred *really*\n");
+    }
+
+    #[test]
+    fn extra_frontmatter_fields_are_written() {
+        let config = ConvertConfig {
+            include_metadata: true,
+            metadata_format: MetadataFormat::Yaml,
+            extra_frontmatter: BTreeMap::from([
+                ("layout".to_string(), "post".to_string()),
+                ("draft".to_string(), "true: yes".to_string()),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(convert("<p>Body</p>", config), "---\ndraft: \"true: yes\"\nlayout: \"post\"\n---\n\nBody\n");
+    }
+
+    #[test]
+    fn blockquote_with_a_paragraph_and_a_list() {
+        let html = "<p>Before</p><blockquote><p>Quoted text</p><ul><li>one</li><li>two<ul><li>deep</li></ul></li></ul></blockquote><p>After</p>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "Before\n\n> Quoted text\n>\n> * one\n> * two\n>   * deep\n\nAfter\n"
+        );
+    }
+
+    #[test]
+    fn nested_blockquotes_stack_markers() {
+        let html = "<blockquote><p>Outer</p><blockquote><p>Inner</p><blockquote><p>Innermost</p></blockquote></blockquote><p>Back</p></blockquote>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "> Outer\n>\n>> Inner\n>>\n>>> Innermost\n>\n> Back\n"
+        );
+        // A quoted line that starts with `>` is content, not another level
+        let html = "<blockquote><pre><code>&gt; not nested\nplain</code></pre></blockquote>";
+        assert_eq!(convert(html, ConvertConfig::default()), "> ```\n> > not nested\n> plain\n> ```\n");
+        let html = "<blockquote><ul><li>item<blockquote><p>inside</p></blockquote></li></ul></blockquote>";
+        assert_eq!(convert(html, ConvertConfig::default()), "> * item\n>\n>   > inside\n");
+    }
+
+    #[test]
+    fn header_alignment_sets_the_rule() {
+        let html = concat!(
+            r#"<table><tr><th align="left">Name</th><th style="text-align: center">Price</th>"#,
+            r#"<th style="text-align:left; text-align: right">Cost</th><th>Note</th></tr>"#,
+            r#"<tr><td>a</td><td>b</td><td>1</td><td>x</td></tr></table>"#,
+        );
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "| Name | Price | Cost | Note |\n| :--- | :---: | ---: | ---- |\n| a    | b     | 1    | x    |\n"
+        );
+    }
+
+    #[test]
+    fn ascii_table_does_not_inherit_alignment() {
+        let html = "<table><tr><th align=\"right\">N</th><th>M</th></tr><tr><td>1</td><td>2</td></tr></table>\
+                    <pre>+---+---+\n| a | b |\n+---+---+\n| 1 | 2 |\n+---+---+</pre>";
+        let config = ConvertConfig { ascii_tables: true, ..Default::default() };
+        assert_eq!(convert(html, config), "| N   | M |\n| --: | - |\n| 1   | 2 |\n\n| a | b |\n| - | - |\n| 1 | 2 |\n");
+    }
+
+    #[test]
+    fn definition_list_gives_one_line_per_definition() {
+        let html = "<p>Intro</p><dl><dt>Rust</dt><dd>A systems language</dd><dd>Also a fungus</dd><dt>Go</dt><dd>Another language</dd></dl><p>After</p>";
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "Intro\n\nRust\n: A systems language\n: Also a fungus\n\nGo\n: Another language\n\nAfter\n"
+        );
+    }
+
+    #[test]
+    fn skipped_heading_levels_are_closed_up() {
+        let headings = || ConvertConfig {
+            normalize_heading_levels: true,
+            preserve_headings: true,
+            max_heading_level: 6,
+            ..Default::default()
+        };
+        let html = "<h1>Title</h1><h4>Deep</h4><h4>Same</h4><h6>Deeper</h6><h2>Back</h2>";
+        assert_eq!(convert(html, headings()), "# Title\n\n## Deep\n\n## Same\n\n### Deeper\n\n## Back\n");
+        // max_heading_level still caps the remapped level
+        let html = "<h1>Title</h1><h4>Deep</h4><h6>Deeper</h6>";
+        assert_eq!(convert(html, ConvertConfig { max_heading_level: 2, ..headings() }), "# Title\n\n## Deep\n");
+    }
+
+    #[test]
+    fn tracking_params_are_stripped() {
+        assert_eq!(
+            strip_tracking_params("https://example.com/a?utm_source=x&q=a%20b&fbclid=1&flag&z=1+2&msclkid=3#top").as_deref(),
+            Some("https://example.com/a?q=a%20b&flag&z=1+2#top")
+        );
+        // No trailing `?` once every parameter is gone
+        assert_eq!(strip_tracking_params("https://example.com/b?utm_medium=m&gclid=2").as_deref(), Some("https://example.com/b"));
+        assert_eq!(strip_tracking_params("https://example.com/c?b=2&a=1"), None);
+        assert_eq!(strip_tracking_params("not a url?utm_source=x"), None);
+
+        let html = r#"<p><a href="https://example.com/a?utm_source=x&amp;q=a%20b&amp;fbclid=1">A</a></p>"#;
+        let config = ConvertConfig { strip_tracking_params: true, include_links: true, ..Default::default() };
+        assert_eq!(convert(html, config), "[A](https://example.com/a?q=a%20b)\n");
+    }
+
+    #[test]
+    fn markdown_characters_are_escaped_in_text() {
+        let html = concat!(
+            r"<p>Use the * operator, [brackets], `ticks`, a \ slash, snake_case_name and _lead_ and &lt;div&gt;</p>",
+            "<p># not a heading</p><p>1. not a list</p><p>- not a bullet</p><p>&gt; not a quote</p>",
+        );
+        assert_eq!(
+            convert(html, ConvertConfig::default()),
+            "Use the \\* operator, \\[brackets\\], \\`ticks\\`, a \\\\ slash, snake_case_name and \\_lead\\_ and \\<div>\n\n\
+             \\# not a heading\n\n1\\. not a list\n\n\\- not a bullet\n\n\\> not a quote\n"
+        );
+        let config = ConvertConfig { escape_markdown: false, ..Default::default() };
+        assert_eq!(convert("<p>Use the * operator</p>", config), "Use the * operator\n");
+    }
+
+    #[test]
+    fn code_and_urls_are_not_escaped() {
+        let html = concat!(
+            r#"<p>Code <code>*ptr_a[0]</code> and <a href="https://example.com/a_b*c">my_[link]</a>"#,
+            r#" and <a href="https://example.com/x_y">https://example.com/x_y</a></p><pre><code>let *a = [1];</code></pre>"#,
+        );
+        let config = ConvertConfig { include_links: true, ..Default::default() };
+        assert_eq!(
+            convert(html, config),
+            "Code `*ptr_a[0]` and [my\\_\\[link\\]](https://example.com/a_b*c) and <https://example.com/x_y>\n\n```\nlet *a = [1];\n```\n"
+        );
+    }
+
+    #[test]
+    fn pipes_in_cells_are_escaped() {
+        let html = "<table><tr><th>Op</th><th>Means</th></tr><tr><td>a|b</td><td>either</td></tr></table>";
+        assert_eq!(convert(html, ConvertConfig::default()), "| Op   | Means  |\n| ---- | ------ |\n| a\\|b | either |\n");
+    }
+
+    #[test]
+    fn reversed_list_with_a_low_start_stops_at_zero() {
+        let html = r#"<ol start="1" reversed><li>a</li><li>b</li><li>c</li></ol>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "1. a\n0. b\n0. c\n");
+        let html = r#"<ol start="0" reversed><li>a</li><li>b</li></ol>"#;
+        assert_eq!(convert(html, ConvertConfig::default()), "0. a\n0. b\n");
+    }
+
+    #[test]
+    fn document_title_appears_once() {
+        let html = "<html><head><title>Page Title</title><style>p{}</style></head><body><p>Body</p></body></html>";
+        assert_eq!(convert(html, ConvertConfig::default()), "Body\n");
+        let markdown = convert(html, ConvertConfig { include_metadata: true, ..Default::default() });
+        assert_eq!(markdown.matches("Page Title").count(), 1, "{}", markdown);
+        assert!(markdown.starts_with("# Page Title\n"), "{}", markdown);
+    }
+
+    fn linked_data_page(json: &str) -> String {
+        let html = format!(
+            r#"<html><head><meta property="og:description" content="OG desc"><script type="application/ld+json">{}</script></head><body><p>Body</p></body></html>"#,
+            json
+        );
+        let config = ConvertConfig {
+            include_metadata: true,
+            metadata_format: MetadataFormat::Yaml,
+            cleaning_rules: CleaningRules { remove_scripts: true, ..Default::default() },
+            ..Default::default()
+        };
+        convert(&html, config)
+    }
+
+    #[test]
+    fn json_ld_article_fills_the_metadata() {
+        let json = r#"{"@context":"https://schema.org","@type":"NewsArticle","headline":"LD Headline","author":[{"@type":"Person","name":"Ann"},"Bob"],"datePublished":"2024-05-01","description":"LD desc","keywords":"rust, web"}"#;
+        assert_eq!(
+            linked_data_page(json),
+            "---\ntitle: \"LD Headline\"\nauthor: \"Ann, Bob\"\ndate: \"2024-05-01\"\ndescription: \"OG desc\"\ntags:\n  - \"rust\"\n  - \"web\"\n---\n\nBody\n"
+        );
+    }
+
+    #[test]
+    fn json_ld_graph_skips_non_articles() {
+        let json = r#"{"@graph":[{"@type":"WebSite","name":"Site","author":"Webmaster"},{"@type":"BlogPosting","headline":"Graph Post","author":{"name":"Cy"},"keywords":["a","b"]}]}"#;
+        assert_eq!(
+            linked_data_page(json),
+            "---\ntitle: \"Graph Post\"\nauthor: \"Cy\"\ndescription: \"OG desc\"\ntags:\n  - \"a\"\n  - \"b\"\n---\n\nBody\n"
+        );
+    }
+
+    #[test]
+    fn malformed_json_ld_falls_back_to_meta_tags() {
+        let markdown = linked_data_page(r#"{"@type":"Article", "headline": "Broken","#);
+        assert_eq!(markdown, "---\ndescription: \"OG desc\"\n---\n\nBody\n");
+    }
+}
@@ -0,0 +1,720 @@
+use worker::*;
+use worker_macros::event;
+use serde::Deserialize;
+use base64::Engine;
+use futures_util::future::{select, Either};
+use futures_util::StreamExt;
+use std::time::Duration;
+use crate::convert::*;
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawConvertRequest")]
+struct ConvertRequest {
+    url: Option<String>,
+    /// Markup to convert in place of fetching `url`, which then only serves as
+    /// the base for relative links.
+    html: Option<String>,
+    config: ConvertConfig,
+}
+
+/// A request as sent. `config` holds only the fields given explicitly, which
+/// override the preset's bundle (or the plain defaults without a preset).
+#[derive(Deserialize)]
+struct RawConvertRequest {
+    #[serde(default)]
+    url: Option<String>,
+    #[serde(default)]
+    html: Option<String>,
+    #[serde(default)]
+    preset: Option<Preset>,
+    #[serde(default)]
+    config: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TryFrom<RawConvertRequest> for ConvertRequest {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawConvertRequest) -> std::result::Result<Self, Self::Error> {
+        if raw.url.is_none() && raw.html.is_none() {
+            return Err(serde::de::Error::custom("either `url` or `html` is required"));
+        }
+        let base = raw.preset.map(Preset::config).unwrap_or_default();
+        let mut config = match serde_json::to_value(base)? {
+            serde_json::Value::Object(fields) => fields,
+            _ => serde_json::Map::new(),
+        };
+        config.extend(raw.config);
+        Ok(ConvertRequest { url: raw.url, html: raw.html, config: serde_json::from_value(config.into())? })
+    }
+}
+
+struct FetchedPage {
+    url: String,
+    body: String,
+    content_type: String,
+    date: Option<String>,
+    /// `Location` of a 3xx response; redirects are followed by the caller.
+    location: Option<String>,
+}
+
+fn page_info(page: &FetchedPage) -> PageInfo {
+    PageInfo {
+        content_type: page.content_type.clone(),
+        charset: declared_charset(&page.content_type, &page.body),
+    }
+}
+
+/// ISO-8601 fetch time from the response `Date` header, falling back to the
+/// worker clock when the header is missing or unparseable.
+fn fetched_at(date_header: Option<&str>) -> String {
+    date_header
+        .and_then(http_date_to_iso)
+        .unwrap_or_else(|| js_sys::Date::new_0().to_iso_string().into())
+}
+
+/// Converts an HTTP `Date` to ISO-8601 in the shape `Date.toISOString()` gives.
+/// Besides IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`) this reads the two
+/// obsolete forms recipients must still accept: RFC 850
+/// (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime (`Sun Nov  6 08:49:37 1994`).
+fn http_date_to_iso(header: &str) -> Option<String> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let full_year = |year: &str| year.parse::<u16>().ok().filter(|_| year.len() == 4);
+    let (day, month, year, time) = match header.split_whitespace().collect::<Vec<_>>()[..] {
+        [_weekday, day, month, year, time, "GMT"] => (day, month, full_year(year)?, time),
+        [_weekday, date, time, "GMT"] => {
+            let [day, month, year] = date.split('-').collect::<Vec<_>>()[..] else {
+                return None;
+            };
+            let year: u16 = year.parse().ok().filter(|_| year.len() == 2)?;
+            // Two-digit years from 70 on are last century's
+            (day, month, if year >= 70 { 1900 + year } else { 2000 + year }, time)
+        }
+        [_weekday, month, day, time, year] => (day, month, full_year(year)?, time),
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|name| *name == month)? + 1;
+    let day: u8 = day.parse().ok().filter(|day| (1..=31).contains(day))?;
+    let [hour, minute, second] = time.split(':').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let field = |value: &str, max: u8| value.parse::<u8>().ok().filter(|value| *value <= max);
+    let (hour, minute, second) = (field(hour, 23)?, field(minute, 59)?, field(second, 60)?);
+    Some(format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.000Z", year, month, day, hour, minute, second))
+}
+
+/// The headers sent with a page fetch: browser-like defaults, a `Referer` for the
+/// page's origin, then the caller's `request_headers` and basic auth. Those last
+/// two only go out with `send_credentials`, i.e. to the origin they were given for.
+fn outgoing_headers(url: &str, config: &ConvertConfig, send_credentials: bool) -> Vec<(String, String)> {
+    let user_agents = [
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+        "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/16.0 Safari/605.1.15",
+        "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+    ];
+
+    let index = user_agents.len() - 1;
+    let user_agent = user_agents[index];
+
+    let mut headers: Vec<(String, String)> = [
+        ("User-Agent", user_agent),
+        ("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8"),
+        ("Accept-Language", "en-US,en;q=0.5"),
+        ("Accept-Encoding", "gzip, deflate, br"),
+        ("Connection", "keep-alive"),
+        ("Upgrade-Insecure-Requests", "1"),
+        ("Sec-Fetch-Dest", "document"),
+        ("Sec-Fetch-Mode", "navigate"),
+        ("Sec-Fetch-Site", "cross-site"),
+        ("Sec-Fetch-User", "?1"),
+        ("Cache-Control", "no-cache"),
+        ("Pragma", "no-cache"),
+        ("DNT", "1"),
+        ("Sec-CH-UA", "\"Google Chrome\";v=\"119\", \"Not)A;Brand\";v=\"24\", \"Chromium\";v=\"119\""),
+        ("Sec-CH-UA-Mobile", "?0"),
+        ("Sec-CH-UA-Platform", "\"Windows\""),
+    ].iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+
+    // Header names are case-insensitive, so a later value replaces any spelling of the name
+    let mut set = |name: &str, value: String| {
+        headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        headers.push((name.to_string(), value));
+    };
+
+    if let Ok(parsed_url) = Url::parse(url) {
+        if let Some(host) = parsed_url.host_str() {
+            set("Referer", format!("{}://{}", parsed_url.scheme(), host));
+        }
+    }
+
+    if send_credentials {
+        // Caller-supplied headers replace the browser-like defaults above
+        for (name, value) in &config.request_headers {
+            set(name, value.clone());
+        }
+
+        if let Some(username) = &config.username {
+            let password = config.password.as_ref().map(|password| password.0.as_str()).unwrap_or_default();
+            let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", username, password));
+            set("Authorization", format!("Basic {}", credentials));
+        }
+    }
+
+    headers
+}
+
+async fn fetch_url_with_timeout(url: &str, timeout_ms: u32, config: &ConvertConfig, send_credentials: bool) -> Result<FetchedPage> {
+    let mut opts = RequestInit::new();
+    opts.method = Method::Get;
+    opts.redirect = RequestRedirect::Manual;
+    opts.headers = Headers::new();
+    for (name, value) in outgoing_headers(url, config, send_credentials) {
+        opts.headers.set(&name, &value)?;
+    }
+
+    let request = Request::new_with_init(url, &opts)?;
+    console_log!("Fetching URL: {}", url);
+
+    let max_retries = 3;
+    let mut retry_count = 0;
+    let mut response = None;
+
+    while retry_count < max_retries {
+        let req = request.clone()?;
+        // The timeout covers a single attempt; a timed-out attempt is retried like any other error
+        let timeout = Delay::from(Duration::from_millis(timeout_ms.into()));
+        let attempt = match select(Box::pin(Fetch::Request(req).send()), timeout).await {
+            Either::Left((result, _)) => result,
+            Either::Right(_) => Err(Error::RustError("request timed out".into())),
+        };
+        match attempt {
+            Ok(mut resp) => {  // Made resp mutable
+                let status = resp.status_code();
+                let content_type = resp.headers().get("content-type")?.unwrap_or_default();
+                let date = resp.headers().get("date")?;
+
+                if status == 403 || status == 429 {
+                    console_error!("Rate limit or access denied, retrying...");
+                    retry_count += 1;
+                    continue;
+                }
+
+                if status == 200 && content_type.contains("text/html") {
+                    let text = read_body(&mut resp, config.max_bytes).await?;
+                    if text.to_lowercase().contains("captcha") {
+                        console_error!("Captcha detected, retrying...");
+                        retry_count += 1;
+                        continue;
+                    }
+                    return Ok(FetchedPage { url: url.to_string(), body: text, content_type, date, location: None });
+                }
+
+                response = Some(resp);
+                break;
+            }
+            Err(e) => {
+                console_error!("Fetch error on attempt {}: {:?}", retry_count + 1, e);
+                retry_count += 1;
+
+                if retry_count == max_retries {
+                    return Err(Error::RustError(format!("Failed to fetch URL after {} attempts: {}", max_retries, e)));
+                }
+
+                Delay::from(Duration::from_millis(1000 * 2_u64.pow(retry_count as u32))).await;
+            }
+        }
+    }
+
+    let mut response = response.ok_or_else(|| {  // Made response mutable
+        Error::RustError("Failed to get valid response after retries".to_string())
+    })?;
+
+    if (300..400).contains(&response.status_code()) {
+        if let Some(location) = response.headers().get("location")? {
+            let content_type = response.headers().get("content-type")?.unwrap_or_default();
+            return Ok(FetchedPage { url: url.to_string(), body: String::new(), content_type, date: None, location: Some(location) });
+        }
+    }
+
+    if response.status_code() >= 400 {
+        console_error!("HTTP error: {}", response.status_code());
+        return Err(Error::RustError(format!("HTTP error: {}", response.status_code())));
+    }
+
+    let content_type = response.headers().get("content-type")?.unwrap_or_default();
+    let date = response.headers().get("date")?;
+
+    let body = read_body(&mut response, config.max_bytes).await.map_err(|e| {
+        console_error!("Text extraction error: {:?}", e);
+        match e {
+            Error::RustError(message) if message == "response too large" => Error::RustError(message),
+            e => Error::RustError(format!("Failed to extract text: {}", e)),
+        }
+    })?;
+
+    Ok(FetchedPage { url: url.to_string(), body, content_type, date, location: None })
+}
+
+/// Reads the body as text, refusing anything over `max_bytes`. A declared
+/// `Content-Length` is checked up front; otherwise the cap applies as the
+/// body streams in.
+async fn read_body(resp: &mut Response, max_bytes: usize) -> Result<String> {
+    let declared = resp.headers().get("content-length")?.and_then(|length| length.trim().parse::<usize>().ok());
+    if declared.is_some_and(|length| length > max_bytes) {
+        return Err(Error::RustError("response too large".into()));
+    }
+
+    let mut stream = resp.stream()?;
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() > max_bytes {
+            return Err(Error::RustError("response too large".into()));
+        }
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn same_origin(a: &str, b: &str) -> bool {
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => a.origin() == b.origin(),
+        _ => false,
+    }
+}
+
+/// Fetches `url`, following up to `max_redirects` hops. Relative `Location`
+/// headers are resolved against the hop that sent them, and the returned
+/// page's `url` is the final one. Credentials are only sent to hops on
+/// `credentials_origin`, the URL the caller asked for.
+async fn fetch_following_redirects(url: &str, credentials_origin: &str, config: &ConvertConfig) -> Result<FetchedPage> {
+    let mut current = url.to_string();
+    let mut visited = vec![current.clone()];
+    loop {
+        let send_credentials = same_origin(&current, credentials_origin);
+        let page = fetch_url_with_timeout(&current, config.timeout_ms, config, send_credentials).await?;
+        let Some(location) = page.location else {
+            return Ok(page);
+        };
+        let next = Url::parse(&current)
+            .and_then(|base| base.join(&location))
+            .map(|url| url.to_string())
+            .map_err(|_| Error::RustError(format!("invalid redirect location: {}", location)))?;
+        if visited.contains(&next) {
+            return Err(Error::RustError(format!("redirect loop at {}", next)));
+        }
+        if visited.len() > config.max_redirects as usize {
+            return Err(Error::RustError(format!("too many redirects (max {})", config.max_redirects)));
+        }
+        console_log!("Redirect {} -> {}", current, next);
+        visited.push(next.clone());
+        current = next;
+    }
+}
+
+
+enum ConvertOutput {
+    Markdown(String),
+    Outline(Vec<OutlineNode>),
+    Sections(Vec<Section>),
+    Inventory(Inventory),
+    Articles(Vec<String>),
+    SourceMap(SourceMapped),
+}
+
+async fn fetch_and_convert(req: ConvertRequest, outline: bool) -> Result<ConvertOutput> {
+    let supplied = req.html.is_some();
+    let mut page = match (req.html, &req.url) {
+        (Some(html), url) => FetchedPage {
+            url: url.clone().unwrap_or_default(),
+            body: html,
+            content_type: "text/html".into(),
+            date: None,
+            location: None,
+        },
+        (None, Some(url)) => fetch_following_redirects(url, url, &req.config).await?,
+        (None, None) => return Err(Error::RustError("either `url` or `html` is required".into())),
+    };
+
+    // Only one hop, so a refresh loop can't keep us fetching. Supplied markup is
+    // converted as given.
+    if req.config.follow_meta_refresh && !supplied {
+        if let Some(target) = meta_refresh_target(&page.body) {
+            let target = Url::parse(&page.url)
+                .and_then(|base| base.join(&target))
+                .map(|url| url.to_string())
+                .unwrap_or(target);
+            console_log!("Following meta refresh to {}", target);
+            // Credentials stay with the requested origin, not wherever the refresh points
+            let requested = req.url.as_deref().unwrap_or_default();
+            page = fetch_following_redirects(&target, requested, &req.config).await?;
+        }
+    }
+
+    if req.config.convert_feeds && !outline && looks_like_feed(&page.content_type, &page.body) {
+        if let Some(markdown) = feed_to_markdown(&page.body, &req.config) {
+            return Ok(ConvertOutput::Markdown(markdown));
+        }
+    }
+
+    if req.config.article_separation == Some(ArticleSeparation::Array) && !outline {
+        return Ok(ConvertOutput::Articles(convert_articles(&page.body, req.config, Some(&page.url))));
+    }
+
+    let mut formatter = convert_document(&page.body, req.config, Some(&page.url));
+
+    if formatter.config.include_fetched_at {
+        formatter.metadata.fetched_at = Some(fetched_at(page.date.as_deref()));
+    }
+
+    if formatter.config.include_metadata && formatter.body_is_empty() {
+        console_warn!("Converted body of {} is empty, emitting metadata only", page.url);
+    }
+
+    if outline {
+        return Ok(ConvertOutput::Outline(formatter.outline()));
+    }
+
+    if formatter.config.source_map {
+        let markdown = formatter.result();
+        let lines = formatter.source_map(&markdown);
+        return Ok(ConvertOutput::SourceMap(SourceMapped { markdown, lines, page: page_info(&page) }));
+    }
+
+    if formatter.config.inventory {
+        let links = std::mem::take(&mut formatter.links);
+        let images = std::mem::take(&mut formatter.images);
+        return Ok(ConvertOutput::Inventory(Inventory { markdown: formatter.result(), links, images, page: page_info(&page) }));
+    }
+
+    match formatter.config.split_by_heading {
+        Some(level) => Ok(ConvertOutput::Sections(split_sections(&formatter.result(), level))),
+        None => Ok(ConvertOutput::Markdown(formatter.result())),
+    }
+}
+
+fn is_script_url(url: &str) -> bool {
+    let scheme = url.trim_start().to_ascii_lowercase();
+    scheme.starts_with("javascript:") || scheme.starts_with("vbscript:")
+}
+
+/// Renders converted markdown into a minimal standalone page for viewing in a browser.
+fn render_html_preview(markdown: &str) -> String {
+    use pulldown_cmark::{Event, Tag};
+
+    let options = pulldown_cmark::Options::ENABLE_TABLES
+        | pulldown_cmark::Options::ENABLE_STRIKETHROUGH
+        | pulldown_cmark::Options::ENABLE_FOOTNOTES;
+    // Page text can decode to markup, so raw HTML is shown escaped rather than
+    // executed, and script URLs are dropped from links and images
+    let parser = pulldown_cmark::Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(html) | Event::InlineHtml(html) => Event::Text(html),
+        Event::Start(Tag::Link { link_type, dest_url, title, id }) if is_script_url(&dest_url) => {
+            Event::Start(Tag::Link { link_type, dest_url: "#".into(), title, id })
+        }
+        Event::Start(Tag::Image { link_type, dest_url, title, id }) if is_script_url(&dest_url) => {
+            Event::Start(Tag::Image { link_type, dest_url: "#".into(), title, id })
+        }
+        event => event,
+    });
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"></head>\n<body>\n");
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Builds a request for `GET /?url=...`. Other query parameters are read as flat
+/// config fields, e.g. `&include_links=true&max_heading_level=3`.
+fn request_from_query(url: &Url) -> std::result::Result<ConvertRequest, String> {
+    use serde_json::Value;
+
+    let mut target = None;
+    let mut preset = None;
+    let mut config = serde_json::Map::new();
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "url" => target = Some(value.into_owned()),
+            "preset" => {
+                preset = Some(serde_json::from_value(Value::String(value.into_owned()))
+                    .map_err(|e| format!("Invalid preset: {}", e))?);
+            }
+            "outline" => {}
+            _ => {
+                let value = match value.as_ref() {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    other => other.parse::<u64>().map(Value::from).ok()
+                        // Float options such as `max_link_density=0.3`
+                        .or_else(|| other.parse::<f64>().ok().filter(|value| value.is_finite()).map(Value::from))
+                        .unwrap_or_else(|| Value::String(other.to_string())),
+                };
+                config.insert(key.into_owned(), value);
+            }
+        }
+    }
+
+    let url = target.filter(|url| !url.trim().is_empty())
+        .ok_or_else(|| "Missing required `url` query parameter".to_string())?;
+    ConvertRequest::try_from(RawConvertRequest { url: Some(url), html: None, preset, config })
+        .map_err(|e| format!("Invalid config parameter: {}", e))
+}
+
+fn response_headers(content_type: &str) -> Headers {
+    Headers::from_iter([
+        ("Access-Control-Allow-Origin", "*"),
+        ("Content-Type", content_type),
+        ("Cache-Control", "public, max-age=3600"),
+    ])
+}
+
+/// SHA-256 over the URL and the serialized config, so any option that changes the
+/// output also changes the key. The URL is length-prefixed to keep the two apart.
+fn cache_key(request: &ConvertRequest) -> String {
+    use sha2::{Digest, Sha256};
+
+    let url = request.url.as_deref().unwrap_or_default();
+    let config = serde_json::to_string(&request.config).unwrap_or_default();
+    let digest = Sha256::new()
+        .chain_update((url.len() as u64).to_le_bytes())
+        .chain_update(url)
+        .chain_update(config)
+        .finalize();
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("md:{}", hex)
+}
+
+fn markdown_response(markdown: String, wants_html: bool) -> Result<Response> {
+    if wants_html {
+        Response::ok(render_html_preview(&markdown))
+            .map(|resp| resp.with_headers(response_headers("text/html; charset=utf-8")))
+    } else {
+        Response::ok(markdown)
+            .map(|resp| resp.with_headers(response_headers("text/markdown; charset=utf-8")))
+    }
+}
+
+fn with_cache_status(response: Result<Response>, status: &str) -> Result<Response> {
+    let mut response = response?;
+    response.headers_mut().set("X-Cache", status)?;
+    Ok(response)
+}
+
+#[event(fetch)]
+pub async fn main(mut req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    console_error_panic_hook::set_once();
+
+    let outline = req.url()?.query_pairs().any(|(key, value)| key == "outline" && value == "true");
+    let wants_html = req.headers().get("Accept")?.is_some_and(|accept| accept.contains("text/html"));
+
+    let request = match req.method() {
+        Method::Post => match req.json::<ConvertRequest>().await {
+            Ok(req) => req,
+            Err(e) => {
+                console_error!("JSON parsing error: {:?}", e);
+                return Response::error(
+                    format!("Invalid request format: {}", e),
+                    400
+                );
+            }
+        },
+        Method::Get => match request_from_query(&req.url()?) {
+            Ok(req) => req,
+            Err(message) => return Response::error(message, 400),
+        },
+        _ => return Response::error("Method Not Allowed", 405),
+    };
+
+    match &request.url {
+        Some(url) if request.html.is_none() => console_log!("Processing URL: {}", url),
+        _ => console_log!("Processing supplied HTML ({} bytes)", request.html.as_deref().map_or(0, str::len)),
+    }
+
+    // Outlines are selected by query parameter rather than config, so they bypass the
+    // cache entirely; so do supplied markup (a KV write per request for one-off
+    // input), a missing binding or a zero TTL.
+    let cache = match env.kv("WEB2MD_CACHE") {
+        Ok(store) if !outline && request.html.is_none() && request.config.cache_ttl > 0 => {
+            Some((store, cache_key(&request), request.config.cache_ttl))
+        }
+        _ => None,
+    };
+
+    if let Some((store, key, _)) = &cache {
+        match store.get(key).text().await {
+            Ok(Some(markdown)) => {
+                return with_cache_status(markdown_response(markdown, wants_html), "HIT");
+            }
+            Ok(None) => {}
+            Err(e) => console_error!("Cache read error: {:?}", e),
+        }
+    }
+
+    match fetch_and_convert(request, outline).await {
+        Ok(ConvertOutput::Markdown(markdown)) => match cache {
+            Some((store, key, ttl)) => {
+                // KV rejects expirations shorter than a minute.
+                let stored = match store.put(&key, markdown.as_str()) {
+                    Ok(put) => put.expiration_ttl(ttl.max(60)).execute().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = stored {
+                    console_error!("Cache write error: {:?}", e);
+                }
+                with_cache_status(markdown_response(markdown, wants_html), "MISS")
+            }
+            None => markdown_response(markdown, wants_html),
+        },
+        Ok(ConvertOutput::Outline(outline)) => {
+            Response::from_json(&outline)
+                .map(|resp| resp.with_headers(response_headers("application/json")))
+        }
+        Ok(ConvertOutput::Sections(sections)) => {
+            Response::from_json(&sections)
+                .map(|resp| resp.with_headers(response_headers("application/json")))
+        }
+        Ok(ConvertOutput::Inventory(inventory)) => {
+            Response::from_json(&inventory)
+                .map(|resp| resp.with_headers(response_headers("application/json")))
+        }
+        Ok(ConvertOutput::Articles(articles)) => {
+            Response::from_json(&articles)
+                .map(|resp| resp.with_headers(response_headers("application/json")))
+        }
+        Ok(ConvertOutput::SourceMap(source_map)) => {
+            Response::from_json(&source_map)
+                .map(|resp| resp.with_headers(response_headers("application/json")))
+        }
+        Err(e) => {
+            console_error!("Conversion error: {:?}", e);
+            Response::error(format!("Conversion failed: {}", e), 500)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        headers.iter().find(|(existing, _)| existing.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn basic_auth_is_sent_but_never_printed() {
+        let config = ConvertConfig {
+            username: Some("user".into()),
+            password: Some(Redacted::from("secret".to_string())),
+            ..Default::default()
+        };
+        let headers = outgoing_headers("https://intranet.example.com/page", &config, true);
+        // base64("user:secret")
+        assert_eq!(header(&headers, "authorization"), Some("Basic dXNlcjpzZWNyZXQ="));
+
+        assert_eq!(format!("{:?}", config.password), "Some([redacted])");
+        assert!(!format!("{:?}", config).contains("secret"));
+
+        let headers = outgoing_headers("https://elsewhere.example.net/", &config, false);
+        assert_eq!(header(&headers, "authorization"), None);
+    }
+
+    #[test]
+    fn html_preview_renders_headings_and_links() {
+        let html = render_html_preview("# Title\n\nSee [the docs](https://example.com/docs).\n");
+        assert!(html.contains("<h1>Title</h1>"), "{}", html);
+        assert!(html.contains(r#"<a href="https://example.com/docs">the docs</a>"#), "{}", html);
+    }
+
+    #[test]
+    fn fetched_at_from_the_date_header() {
+        assert_eq!(http_date_to_iso("Sun, 06 Nov 1994 08:49:37 GMT").as_deref(), Some("1994-11-06T08:49:37.000Z"));
+        assert_eq!(http_date_to_iso("Wed, 15 Oct 2026 23:05:09 GMT").as_deref(), Some("2026-10-15T23:05:09.000Z"));
+        assert_eq!(http_date_to_iso("Sunday, 06-Nov-94 08:49:37 GMT").as_deref(), Some("1994-11-06T08:49:37.000Z"));
+        assert_eq!(http_date_to_iso("Thursday, 15-Oct-26 23:05:09 GMT").as_deref(), Some("2026-10-15T23:05:09.000Z"));
+        assert_eq!(http_date_to_iso("Sun Nov  6 08:49:37 1994").as_deref(), Some("1994-11-06T08:49:37.000Z"));
+        assert_eq!(http_date_to_iso("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(http_date_to_iso("Sun, 06 Nov 1994 25:49:37 GMT"), None);
+        assert_eq!(http_date_to_iso("garbage"), None);
+    }
+
+    #[test]
+    fn fetched_at_appears_in_metadata() {
+        let config = ConvertConfig { include_metadata: true, metadata_format: MetadataFormat::Yaml, ..Default::default() };
+        let mut formatter = convert_document("<p>Body</p>", config, None);
+        formatter.metadata.fetched_at = http_date_to_iso("Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(formatter.result().starts_with("---\nfetched_at: \"1994-11-06T08:49:37.000Z\"\n---\n"));
+    }
+
+    #[test]
+    fn custom_accept_header_overrides_the_default() {
+        let config = ConvertConfig {
+            request_headers: std::collections::BTreeMap::from([
+                ("accept".to_string(), "text/plain".to_string()),
+                ("X-Api-Key".to_string(), "k".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let headers = outgoing_headers("https://example.com/", &config, true);
+        assert_eq!(headers.iter().filter(|(name, _)| name.eq_ignore_ascii_case("accept")).count(), 1);
+        assert_eq!(header(&headers, "Accept"), Some("text/plain"));
+        assert_eq!(header(&headers, "x-api-key"), Some("k"));
+        assert_eq!(header(&headers, "Accept-Language"), Some("en-US,en;q=0.5"));
+    }
+
+    #[test]
+    fn explicit_fields_override_the_preset() {
+        let request = |body: serde_json::Value| -> ConvertRequest {
+            serde_json::from_value::<RawConvertRequest>(body).unwrap().try_into().unwrap()
+        };
+
+        let article = request(serde_json::json!({ "url": "https://example.com/", "preset": "article" }));
+        assert!(article.config.preserve_headings && article.config.include_metadata && article.config.skip_empty_blocks);
+        assert_eq!(article.config.max_heading_level, 6);
+
+        let overridden = request(serde_json::json!({
+            "url": "https://example.com/",
+            "preset": "article",
+            "config": { "include_metadata": false, "max_heading_level": 3 },
+        }));
+        assert!(!overridden.config.include_metadata && overridden.config.preserve_headings);
+        assert_eq!(overridden.config.max_heading_level, 3);
+
+        let missing: RawConvertRequest = serde_json::from_value(serde_json::json!({ "preset": "article" })).unwrap();
+        assert!(ConvertRequest::try_from(missing).is_err());
+    }
+
+    #[test]
+    fn query_parameters_parse_bools_integers_and_floats() {
+        let url = Url::parse("https://worker.dev/?url=https://example.com/&max_link_density=0.3&max_heading_level=3&include_links=true&quote_locale=fr").unwrap();
+        let request = request_from_query(&url).unwrap();
+        assert_eq!(request.url.as_deref(), Some("https://example.com/"));
+        assert_eq!(request.config.max_link_density, Some(0.3));
+        assert_eq!(request.config.max_heading_level, 3);
+        assert!(request.config.include_links);
+        assert_eq!(request.config.quote_locale.as_deref(), Some("fr"));
+
+        let url = Url::parse("https://worker.dev/?url=https://example.com/&max_link_density=NaN").unwrap();
+        assert!(request_from_query(&url).is_err());
+    }
+
+    /// Drives a conversion of supplied HTML, which never waits on I/O.
+    fn convert_supplied<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        match future.as_mut().poll(&mut std::task::Context::from_waker(std::task::Waker::noop())) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("supplied HTML should convert without waiting"),
+        }
+    }
+
+    #[test]
+    fn inventory_reports_the_page_charset_and_content_type() {
+        let html = r#"<html><head><meta charset="windows-1252"></head><body><p>Caf&eacute; <a href="/menu">menu</a></p></body></html>"#;
+        let config = ConvertConfig { inventory: true, include_links: true, ..Default::default() };
+        let request = ConvertRequest { url: Some("https://example.com/".into()), html: Some(html.into()), config };
+        let Ok(ConvertOutput::Inventory(inventory)) = convert_supplied(fetch_and_convert(request, false)) else {
+            panic!("expected an inventory");
+        };
+        let json = serde_json::to_value(&inventory).unwrap();
+        assert_eq!(json["page"], serde_json::json!({ "content_type": "text/html", "charset": "windows-1252" }));
+        assert_eq!(json["links"][0]["href"], "https://example.com/menu");
+    }
+}